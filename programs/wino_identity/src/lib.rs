@@ -1,13 +1,165 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::token_interface::{
+    self, transfer_checked, CloseAccount, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+use unicode_normalization::UnicodeNormalization;
+
+/// `msg!` that is only logged when built with the `verbose-logs` feature.
+/// Indexers should rely on the events emitted through `emit_cpi!` instead.
+macro_rules! verbose_msg {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "verbose-logs") {
+            msg!($($arg)*);
+        }
+    };
+}
 
 declare_id!("6oFvAzVT24jz9BJgJUtvorLD2SEZddGFhSSLu246JVt5");
 
+/// Seeds for deriving the program config PDA (singleton)
+pub const CONFIG_SEED: &[u8] = b"wino_config";
+
+/// Pause flags in `ProgramConfig::paused`, one bit per instruction group.
+/// Every instruction checks its flag, except these, which only lower risk
+/// or are needed while handling an incident:
+/// - admin instructions, including the verifier allowlist and handle
+///   reservations
+/// - `revoke_attestation`, `revoke_terminal`, `close_terminal` and
+///   `cancel_recovery`
+/// - `cancel_invoice`, `close_expired_invoice` and `open_dispute`
+/// - `ping_terminal`, which only records that a device is online
+pub const PAUSE_CREATE: u8 = 1 << 0;
+pub const PAUSE_UPDATES: u8 = 1 << 1;
+pub const PAUSE_PAYMENTS: u8 = 1 << 2;
+pub const PAUSE_ALL: u8 = PAUSE_CREATE | PAUSE_UPDATES | PAUSE_PAYMENTS;
+
 /// Seeds for deriving the identity PDA
 pub const IDENTITY_SEED: &[u8] = b"wino_business_identity";
 
+/// Seeds for deriving the authority -> identity pointer PDA
+pub const IDENTITY_POINTER_SEED: &[u8] = b"wino_identity_pointer";
+
+/// Seeds for deriving the name registry PDA, followed by the name hash
+pub const NAME_RECORD_SEED: &[u8] = b"wino_name_record";
+
+/// Seeds for deriving the @handle PDA, followed by the handle
+pub const HANDLE_RECORD_SEED: &[u8] = b"wino_handle";
+
+/// Seeds for deriving a reserved handle PDA, followed by the handle
+pub const RESERVED_HANDLE_SEED: &[u8] = b"wino_reserved_handle";
+
+/// Seeds for deriving an allowlisted verifier PDA, followed by its key
+pub const VERIFIER_SEED: &[u8] = b"wino_verifier";
+
+/// Seeds for deriving a staff member PDA, followed by identity and staff key
+pub const STAFF_SEED: &[u8] = b"wino_staff";
+
+/// Staff role flags in `StaffMember::roles`
+pub const ROLE_CREATE_INVOICES: u8 = 1 << 0;
+pub const ROLE_ISSUE_REFUNDS: u8 = 1 << 1;
+pub const ROLE_EDIT_PROFILE: u8 = 1 << 2;
+pub const ROLE_MANAGE_STAFF: u8 = 1 << 3;
+pub const ALL_ROLES: u8 =
+    ROLE_CREATE_INVOICES | ROLE_ISSUE_REFUNDS | ROLE_EDIT_PROFILE | ROLE_MANAGE_STAFF;
+
+/// Seeds for deriving the guardian set PDA of an identity
+pub const GUARDIAN_SET_SEED: &[u8] = b"wino_guardians";
+
+/// Seeds for deriving the recovery PDA of an identity
+pub const RECOVERY_SEED: &[u8] = b"wino_recovery";
+
+/// Guardian and recovery limits
+pub const MAX_GUARDIANS: usize = 10;
+pub const MIN_RECOVERY_TIMELOCK: i64 = 24 * 60 * 60;
+pub const MAX_RECOVERY_TIMELOCK: i64 = 30 * 24 * 60 * 60;
+
+/// Seeds for deriving a multisig PDA, followed by its create key
+pub const MULTISIG_SEED: &[u8] = b"wino_multisig";
+
+/// Seeds for deriving the signer PDA of a multisig, used as identity authority
+pub const MULTISIG_SIGNER_SEED: &[u8] = b"wino_multisig_signer";
+
+/// Seeds for deriving a proposal PDA, followed by multisig and index
+pub const PROPOSAL_SEED: &[u8] = b"wino_proposal";
+
+/// Multisig and proposal limits
+pub const MAX_MULTISIG_MEMBERS: usize = 10;
+pub const MAX_PROPOSAL_ACCOUNTS: usize = 24;
+pub const MAX_PROPOSAL_DATA_LENGTH: usize = 768;
+
+/// Seeds for deriving an attestation PDA, followed by identity, verifier
+/// and claim type
+pub const ATTESTATION_SEED: &[u8] = b"wino_attestation";
+
+/// Current layout version of `BusinessIdentity`
+pub const IDENTITY_VERSION: u8 = 2;
+
+/// Version of the original layout, which had no version byte. Its
+/// `identity_type` (always 1) sits where `version` is stored now.
+pub const LEGACY_IDENTITY_VERSION: u8 = 1;
+
 /// Maximum lengths for strings
-pub const MAX_NAME_LENGTH: usize = 64;
+pub const MAX_NAME_LENGTH: usize = 128;
 pub const MAX_LOGO_URI_LENGTH: usize = 200;
+pub const MAX_LOGO_MIME_LENGTH: usize = 32;
+pub const MAX_DESCRIPTION_LENGTH: usize = 280;
+pub const MAX_WEBSITE_LENGTH: usize = 200;
+pub const MAX_SUPPORT_CONTACT_LENGTH: usize = 100;
+pub const MAX_BUSINESS_CATEGORY_LENGTH: usize = 32;
+pub const MAX_OPENING_HOURS_LENGTH: usize = 128;
+
+/// Names are limited by characters as displayed, on top of the byte limit
+pub const MAX_NAME_CHARS: usize = 64;
+
+/// URI schemes accepted for `logo_uri`
+pub const ALLOWED_LOGO_URI_SCHEMES: [&str; 3] = ["https://", "ar://", "ipfs://"];
+
+/// URI scheme required for `website`
+pub const WEBSITE_SCHEME: &str = "https://";
+
+/// ISO 3166-1 alpha-2 country codes are exactly two letters
+pub const COUNTRY_CODE_LENGTH: usize = 2;
+
+/// Seeds for deriving a store PDA, followed by identity and store index
+pub const STORE_SEED: &[u8] = b"wino_store";
+
+/// Store address limits; geohashes use the standard base32 alphabet
+pub const MAX_STORE_ADDRESS_LENGTH: usize = 128;
+pub const MAX_GEOHASH_LENGTH: usize = 12;
+
+/// Seeds for deriving a terminal PDA, followed by identity and device key
+pub const TERMINAL_SEED: &[u8] = b"wino_terminal";
+
+/// Maximum terminal label length in bytes
+pub const MAX_TERMINAL_LABEL_LENGTH: usize = 32;
+
+/// Seeds for deriving an invoice PDA, followed by identity and reference
+pub const INVOICE_SEED: &[u8] = b"wino_invoice";
+
+/// Seeds for deriving a refund PDA, followed by invoice and refund index
+pub const REFUND_SEED: &[u8] = b"wino_refund";
+
+/// Seeds for deriving the escrow vault of an invoice, followed by the invoice
+pub const ESCROW_VAULT_SEED: &[u8] = b"wino_escrow_vault";
+
+/// Bounds of the escrow release window, in seconds
+pub const MIN_ESCROW_RELEASE_WINDOW: i64 = 60 * 60;
+pub const MAX_ESCROW_RELEASE_WINDOW: i64 = 90 * 24 * 60 * 60;
+
+/// Maximum invoice memo length in bytes
+pub const MAX_INVOICE_MEMO_LENGTH: usize = 64;
+
+/// Maximum number of per-mint settlement destinations on an identity or store
+pub const MAX_SETTLEMENT_ACCOUNTS: usize = 8;
+
+/// Handle length bounds (a handle is also used as a PDA seed, max 32 bytes)
+pub const MIN_HANDLE_LENGTH: usize = 3;
+pub const MAX_HANDLE_LENGTH: usize = 20;
 
 #[program]
 pub mod wino_identity {
@@ -16,132 +168,6462 @@ pub mod wino_identity {
     /// Create a new business identity PDA
     ///
     /// This creates a unique identity account for a wallet.
-    /// Each wallet can only have ONE identity.
+    /// Each wallet can only hold ONE identity at a time, and the name is
+    /// claimed in the registry so no other identity can use it.
+    /// The PDA is derived from a one-off `create_key` rather than the
+    /// wallet, so a wallet that transferred its identity away can create
+    /// a new one.
+    /// `identity_type` is the discriminant of an [`IdentityType`].
+    /// `logo_sha256` and `logo_mime` commit to the image at `logo_uri`.
     pub fn create_identity(
         ctx: Context<CreateIdentity>,
+        identity_type: u8,
         name: String,
         logo_uri: String,
+        logo_sha256: Option<[u8; 32]>,
+        logo_mime: Option<String>,
     ) -> Result<()> {
+        let kind = IdentityType::try_from(identity_type)?;
+        let name = validate_name(&name)?.to_string();
+        validate_logo_uri(&logo_uri)?;
+        validate_logo_commitment(&logo_uri, &logo_sha256, &logo_mime)?;
         require!(
-            name.len() > 0 && name.len() <= MAX_NAME_LENGTH,
-            IdentityError::InvalidNameLength
-        );
-        require!(
-            logo_uri.len() <= MAX_LOGO_URI_LENGTH,
-            IdentityError::InvalidLogoUriLength
+            !kind.requires_logo() || !logo_uri.is_empty(),
+            IdentityError::LogoRequired
         );
 
         let identity = &mut ctx.accounts.identity;
         let clock = Clock::get()?;
 
         identity.authority = ctx.accounts.authority.key();
-        identity.identity_type = 1; // 1 = business
+        identity.version = IDENTITY_VERSION;
+        identity.identity_type = kind as u8;
         identity.name = name;
         identity.logo_uri = logo_uri;
+        identity.logo_sha256 = logo_sha256;
+        identity.logo_mime = logo_mime;
         identity.created_at = clock.unix_timestamp;
         identity.updated_at = clock.unix_timestamp;
         identity.bump = ctx.bumps.identity;
+        identity.creator = ctx.accounts.create_key.key();
+        identity.pending_authority = None;
+
+        let pointer = &mut ctx.accounts.pointer;
+        pointer.identity = identity.key();
+        pointer.bump = ctx.bumps.pointer;
+
+        let name_record = &mut ctx.accounts.name_record;
+        name_record.identity = identity.key();
+        name_record.bump = ctx.bumps.name_record;
 
-        msg!("Business identity created for: {}", identity.authority);
-        msg!("Name: {}", identity.name);
-        msg!("PDA: {}", ctx.accounts.identity.key());
+        emit_cpi!(IdentityCreated {
+            identity: identity.key(),
+            authority: identity.authority,
+            identity_type: identity.identity_type,
+            name: identity.name.clone(),
+            logo_uri: identity.logo_uri.clone(),
+            logo_sha256: identity.logo_sha256,
+            created_at: identity.created_at,
+        });
+
+        verbose_msg!("Business identity created for: {}", identity.authority);
+        verbose_msg!("Name: {}", identity.name);
+        verbose_msg!("PDA: {}", ctx.accounts.identity.key());
 
         Ok(())
     }
 
     /// Update an existing business identity
     ///
-    /// Only the original authority can update their identity.
+    /// Signed by the current authority or by a staff member with
+    /// `ROLE_EDIT_PROFILE`, who then pays for any growth. Fields left
+    /// as `None` keep their stored value; an empty string clears an
+    /// optional profile field. `logo_sha256` and `logo_mime` replace the
+    /// stored logo commitment and can only be given together with
+    /// `logo_uri`. When `expected_updated_at` is set, the update is
+    /// rejected if the identity changed since the client read it.
+    ///
+    /// Renaming releases the registry record of the old name and claims the
+    /// one of the new name, which must then be passed as `new_name_record`.
+    ///
+    /// The account is resized to fit its new contents, with the signer
+    /// paying for growth. Rent freed by shrinking or by releasing the old
+    /// name always goes to the identity authority.
     pub fn update_identity(
         ctx: Context<UpdateIdentity>,
-        name: String,
-        logo_uri: String,
+        name: Option<String>,
+        logo_uri: Option<String>,
+        logo_sha256: Option<[u8; 32]>,
+        logo_mime: Option<String>,
+        profile: ProfileUpdate,
+        expected_updated_at: Option<i64>,
     ) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        if let Some(expected_updated_at) = expected_updated_at {
+            require!(
+                identity.updated_at == expected_updated_at,
+                IdentityError::StaleIdentity
+            );
+        }
+        let old_name = identity.name.clone();
+        let old_logo_uri = identity.logo_uri.clone();
+        let old_logo_sha256 = identity.logo_sha256;
+        let old_logo_mime = identity.logo_mime.clone();
+        let old_profile = identity.profile();
+
+        if let Some(name) = name {
+            let name = validate_name(&name)?.to_string();
+            let renamed = normalized_name_hash(&name) != normalized_name_hash(&identity.name);
+            if renamed {
+                release_name(
+                    &ctx.accounts.current_name_record,
+                    identity.key(),
+                    &ctx.accounts.rent_destination,
+                )?;
+            }
+            match ctx.accounts.new_name_record.as_mut() {
+                Some(new_name_record) => {
+                    new_name_record.identity = identity.key();
+                    new_name_record.bump = ctx.bumps.new_name_record.unwrap_or_default();
+                }
+                None => require!(!renamed, IdentityError::NameRecordRequired),
+            }
+            identity.name = name;
+        }
+        match logo_uri {
+            Some(logo_uri) => {
+                validate_logo_uri(&logo_uri)?;
+                validate_logo_commitment(&logo_uri, &logo_sha256, &logo_mime)?;
+                identity.logo_uri = logo_uri;
+                identity.logo_sha256 = logo_sha256;
+                identity.logo_mime = logo_mime;
+            }
+            None => require!(
+                logo_sha256.is_none() && logo_mime.is_none(),
+                IdentityError::LogoCommitmentWithoutUri
+            ),
+        }
+        identity.apply_profile(profile.clone())?;
         require!(
-            name.len() > 0 && name.len() <= MAX_NAME_LENGTH,
-            IdentityError::InvalidNameLength
+            !identity.kind()?.requires_logo() || !identity.logo_uri.is_empty(),
+            IdentityError::LogoRequired
         );
+
+        let clock = Clock::get()?;
+        identity.updated_at = clock.unix_timestamp;
+
+        resize_account(
+            &identity.to_account_info(),
+            identity.space(),
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.rent_destination,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_cpi!(IdentityUpdated {
+            identity: identity.key(),
+            authority: identity.authority,
+            old_name,
+            new_name: identity.name.clone(),
+            old_logo_uri,
+            new_logo_uri: identity.logo_uri.clone(),
+            old_logo_sha256,
+            new_logo_sha256: identity.logo_sha256,
+            old_logo_mime,
+            new_logo_mime: identity.logo_mime.clone(),
+            profile,
+            old_profile,
+            new_profile: identity.profile(),
+            updated_at: identity.updated_at,
+        });
+
+        verbose_msg!("Business identity updated for: {}", identity.authority);
+        verbose_msg!("Name: {}", identity.name);
+
+        Ok(())
+    }
+
+    /// Close a business identity and reclaim its rent
+    ///
+    /// Only the current authority can close their identity. The rent of
+    /// the identity and its pointer is sent to `destination`, and the name
+    /// and @handle are released. Stores must be closed first, and escrows
+    /// still to be settled block closing.
+    pub fn close_identity(ctx: Context<CloseIdentity>) -> Result<()> {
+        let identity = &ctx.accounts.identity;
+        let clock = Clock::get()?;
+
+        release_name(
+            &ctx.accounts.name_record,
+            identity.key(),
+            &ctx.accounts.destination,
+        )?;
         require!(
-            logo_uri.len() <= MAX_LOGO_URI_LENGTH,
-            IdentityError::InvalidLogoUriLength
+            identity.handle.is_none() || ctx.accounts.handle_record.is_some(),
+            IdentityError::HandleRecordRequired
+        );
+
+        emit_cpi!(IdentityClosed {
+            identity: identity.key(),
+            authority: identity.authority,
+            destination: ctx.accounts.destination.key(),
+            closed_at: clock.unix_timestamp,
+        });
+
+        verbose_msg!("Business identity closed for: {}", identity.authority);
+        verbose_msg!("Rent sent to: {}", ctx.accounts.destination.key());
+
+        Ok(())
+    }
+
+    /// Replace the settlement destinations of an identity
+    ///
+    /// Only the current authority can change where payments go. Each
+    /// token destination is passed as a (mint, token account) pair of
+    /// remaining accounts and must be a token account of that mint; at
+    /// most one destination per mint is allowed.
+    pub fn set_settlement<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetSettlement<'info>>,
+        settlement_sol: Option<Pubkey>,
+    ) -> Result<()> {
+        let settlement_accounts = parse_settlement(settlement_sol, ctx.remaining_accounts)?;
+
+        let identity = &mut ctx.accounts.identity;
+        identity.settlement_sol = settlement_sol;
+        identity.settlement_accounts = settlement_accounts;
+        identity.updated_at = Clock::get()?.unix_timestamp;
+
+        let authority = ctx.accounts.authority.to_account_info();
+        resize_account(
+            &identity.to_account_info(),
+            identity.space(),
+            &authority,
+            &authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_cpi!(SettlementUpdated {
+            identity: identity.key(),
+            store: None,
+            settlement_sol,
+            settlement_accounts: identity.settlement_accounts.clone(),
+        });
+
+        verbose_msg!(
+            "Settlement updated with {} token destinations",
+            identity.settlement_accounts.len()
         );
 
+        Ok(())
+    }
+
+    /// Create a store (location) under an identity
+    ///
+    /// Only the identity authority can add stores. Stores are numbered
+    /// from 0 in creation order and start out active.
+    pub fn create_store(
+        ctx: Context<CreateStore>,
+        name: String,
+        address: String,
+        geohash: Option<String>,
+    ) -> Result<()> {
+        let name = validate_name(&name)?.to_string();
+        validate_store_location(&address, &geohash)?;
+
         let identity = &mut ctx.accounts.identity;
         let clock = Clock::get()?;
+        let store = &mut ctx.accounts.store;
+        store.identity = identity.key();
+        store.index = identity.store_count;
+        store.name = name;
+        store.address = address;
+        store.geohash = geohash;
+        store.settlement_sol = None;
+        store.settlement_accounts = Vec::new();
+        store.active = true;
+        store.open_escrows = 0;
+        store.created_at = clock.unix_timestamp;
+        store.updated_at = clock.unix_timestamp;
+        store.bump = ctx.bumps.store;
+        identity.store_count += 1;
+        identity.open_stores += 1;
 
-        identity.name = name;
-        identity.logo_uri = logo_uri;
+        emit_cpi!(StoreUpdated {
+            identity: identity.key(),
+            store: store.key(),
+            index: store.index,
+            name: store.name.clone(),
+            address: store.address.clone(),
+            geohash: store.geohash.clone(),
+            active: true,
+        });
+
+        verbose_msg!("Store {} created: {}", store.index, store.name);
+
+        Ok(())
+    }
+
+    /// Update the name or location of a store
+    ///
+    /// Signed by the identity authority or a staff member with
+    /// `ROLE_EDIT_PROFILE`. Fields left as `None` are kept, and an empty
+    /// geohash clears it.
+    pub fn update_store(
+        ctx: Context<UpdateStore>,
+        name: Option<String>,
+        address: Option<String>,
+        geohash: Option<String>,
+    ) -> Result<()> {
+        let store = &mut ctx.accounts.store;
+        if let Some(name) = name {
+            store.name = validate_name(&name)?.to_string();
+        }
+        if let Some(address) = address {
+            store.address = address;
+        }
+        if let Some(geohash) = geohash {
+            store.geohash = (!geohash.is_empty()).then_some(geohash);
+        }
+        validate_store_location(&store.address, &store.geohash)?;
+        store.updated_at = Clock::get()?.unix_timestamp;
+
+        emit_cpi!(StoreUpdated {
+            identity: store.identity,
+            store: store.key(),
+            index: store.index,
+            name: store.name.clone(),
+            address: store.address.clone(),
+            geohash: store.geohash.clone(),
+            active: store.active,
+        });
+
+        verbose_msg!("Store {} updated", store.index);
+
+        Ok(())
+    }
+
+    /// Replace the settlement destinations a store uses instead of the
+    /// identity's, as in [`set_settlement`]
+    ///
+    /// Mints without a store destination settle to the identity's.
+    pub fn set_store_settlement<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetStoreSettlement<'info>>,
+        settlement_sol: Option<Pubkey>,
+    ) -> Result<()> {
+        let settlement_accounts = parse_settlement(settlement_sol, ctx.remaining_accounts)?;
+
+        let store = &mut ctx.accounts.store;
+        store.settlement_sol = settlement_sol;
+        store.settlement_accounts = settlement_accounts;
+        store.updated_at = Clock::get()?.unix_timestamp;
+
+        emit_cpi!(SettlementUpdated {
+            identity: store.identity,
+            store: Some(store.key()),
+            settlement_sol,
+            settlement_accounts: store.settlement_accounts.clone(),
+        });
+
+        verbose_msg!("Settlement updated for store {}", store.index);
+
+        Ok(())
+    }
+
+    /// Deactivate a store so it no longer takes payments
+    ///
+    /// Only the identity authority can deactivate stores. The account is
+    /// kept so past invoices stay attributable until it is closed with
+    /// [`close_store`].
+    pub fn deactivate_store(ctx: Context<DeactivateStore>) -> Result<()> {
+        let store = &mut ctx.accounts.store;
+        store.active = false;
+        store.updated_at = Clock::get()?.unix_timestamp;
+
+        emit_cpi!(StoreUpdated {
+            identity: store.identity,
+            store: store.key(),
+            index: store.index,
+            name: store.name.clone(),
+            address: store.address.clone(),
+            geohash: store.geohash.clone(),
+            active: false,
+        });
+
+        verbose_msg!("Store {} deactivated", store.index);
+
+        Ok(())
+    }
+
+    /// Close a deactivated store and return its rent to the authority
+    ///
+    /// Only the identity authority can close stores, and only once no
+    /// escrow of the store awaits settlement. Open invoices of the store
+    /// can no longer be paid afterwards.
+    pub fn close_store(ctx: Context<CloseStore>) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        identity.open_stores -= 1;
+
+        let store = &ctx.accounts.store;
+        emit_cpi!(StoreClosed {
+            identity: identity.key(),
+            store: store.key(),
+            index: store.index,
+        });
+
+        verbose_msg!("Store {} closed", store.index);
+
+        Ok(())
+    }
+
+    /// Register a POS device key as a terminal of the identity
+    ///
+    /// Only the identity authority can register terminals. Passing a
+    /// `store` attributes the terminal to that location. A device whose
+    /// terminal was revoked or registered by a previous authority can be
+    /// registered again.
+    pub fn register_terminal(
+        ctx: Context<RegisterTerminal>,
+        device: Pubkey,
+        label: String,
+    ) -> Result<()> {
+        require!(
+            !label.is_empty() && label.len() <= MAX_TERMINAL_LABEL_LENGTH,
+            IdentityError::InvalidTerminalLabel
+        );
+        require_keys_neq!(
+            device,
+            ctx.accounts.identity.authority,
+            IdentityError::InvalidTerminalDevice
+        );
+
+        let clock = Clock::get()?;
+        let terminal = &mut ctx.accounts.terminal;
+        terminal.identity = ctx.accounts.identity.key();
+        terminal.authority = ctx.accounts.identity.authority;
+        terminal.store = ctx.accounts.store.as_ref().map(|store| store.key());
+        terminal.device = device;
+        terminal.label = label;
+        terminal.last_seen_slot = clock.slot;
+        terminal.revoked = false;
+        terminal.created_at = clock.unix_timestamp;
+        terminal.bump = ctx.bumps.terminal;
+
+        emit_cpi!(TerminalRegistered {
+            identity: terminal.identity,
+            terminal: terminal.key(),
+            store: terminal.store,
+            device,
+            label: terminal.label.clone(),
+        });
+
+        verbose_msg!("Terminal registered: {}", device);
+
+        Ok(())
+    }
+
+    /// Revoke a terminal, e.g. after its device was lost or stolen
+    ///
+    /// Only the identity authority can revoke terminals. The device stays
+    /// revoked until it is registered again.
+    pub fn revoke_terminal(ctx: Context<RevokeTerminal>) -> Result<()> {
+        let terminal = &mut ctx.accounts.terminal;
+        terminal.revoked = true;
+
+        emit_cpi!(TerminalRevoked {
+            identity: terminal.identity,
+            terminal: terminal.key(),
+            device: terminal.device,
+        });
+
+        verbose_msg!("Terminal revoked: {}", terminal.device);
+
+        Ok(())
+    }
+
+    /// Close a terminal and return its rent to the identity authority
+    ///
+    /// Only the identity authority can close terminals, whether active,
+    /// revoked or registered by a previous authority.
+    pub fn close_terminal(ctx: Context<CloseTerminal>) -> Result<()> {
+        let terminal = &ctx.accounts.terminal;
+
+        emit_cpi!(TerminalClosed {
+            identity: terminal.identity,
+            terminal: terminal.key(),
+            device: terminal.device,
+        });
+
+        verbose_msg!("Terminal closed: {}", terminal.device);
+
+        Ok(())
+    }
+
+    /// Record that a terminal is online
+    ///
+    /// Signed by the terminal's device key.
+    pub fn ping_terminal(ctx: Context<PingTerminal>) -> Result<()> {
+        ctx.accounts.terminal.last_seen_slot = Clock::get()?.slot;
+        Ok(())
+    }
+
+    /// Propose handing the identity over to a new wallet
+    ///
+    /// The transfer only takes effect once `new_authority` accepts it.
+    /// Proposing again replaces any pending proposal.
+    pub fn propose_authority_transfer(
+        ctx: Context<ProposeAuthorityTransfer>,
+        new_authority: Pubkey,
+    ) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        require_keys_neq!(
+            new_authority,
+            identity.authority,
+            IdentityError::InvalidNewAuthority
+        );
+
+        identity.pending_authority = Some(new_authority);
+
+        emit_cpi!(AuthorityTransferProposed {
+            identity: identity.key(),
+            authority: identity.authority,
+            pending_authority: new_authority,
+        });
+
+        verbose_msg!("Authority transfer proposed to: {}", new_authority);
+
+        Ok(())
+    }
+
+    /// Withdraw a pending authority transfer
+    pub fn cancel_authority_transfer(ctx: Context<CancelAuthorityTransfer>) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        let pending_authority = identity
+            .pending_authority
+            .take()
+            .ok_or(IdentityError::NoPendingAuthorityTransfer)?;
+
+        emit_cpi!(AuthorityTransferCancelled {
+            identity: identity.key(),
+            authority: identity.authority,
+            pending_authority,
+        });
+
+        verbose_msg!("Authority transfer to {} cancelled", pending_authority);
+
+        Ok(())
+    }
+
+    /// Accept a pending authority transfer
+    ///
+    /// Must be signed by the proposed wallet. The pointer of the previous
+    /// authority is closed and a new one is created for the new authority,
+    /// which must not already own an identity.
+    pub fn accept_authority_transfer(ctx: Context<AcceptAuthorityTransfer>) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        let clock = Clock::get()?;
+        let previous_authority = identity.authority;
+
+        identity.authority = ctx.accounts.new_authority.key();
+        identity.pending_authority = None;
         identity.updated_at = clock.unix_timestamp;
 
-        msg!("Business identity updated for: {}", identity.authority);
-        msg!("New name: {}", identity.name);
+        let pointer = &mut ctx.accounts.new_pointer;
+        pointer.identity = identity.key();
+        pointer.bump = ctx.bumps.new_pointer;
+
+        emit_cpi!(AuthorityTransferred {
+            identity: identity.key(),
+            previous_authority,
+            new_authority: identity.authority,
+        });
+
+        verbose_msg!(
+            "Authority transferred from {} to {}",
+            previous_authority,
+            identity.authority
+        );
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct CreateIdentity<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = BusinessIdentity::SIZE,
-        seeds = [IDENTITY_SEED, authority.key().as_ref()],
-        bump
-    )]
-    pub identity: Account<'info, BusinessIdentity>,
+    /// Create the program config and set its first admin
+    ///
+    /// Can only be called once, by the program's upgrade authority.
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.pending_admin = None;
+        config.bump = ctx.bumps.config;
+        config.paused = 0;
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        emit_cpi!(ConfigInitialized { admin });
 
-    pub system_program: Program<'info, System>,
-}
+        verbose_msg!("Program config initialized with admin: {}", admin);
 
-#[derive(Accounts)]
-pub struct UpdateIdentity<'info> {
-    #[account(
-        mut,
-        seeds = [IDENTITY_SEED, authority.key().as_ref()],
-        bump = identity.bump,
-        constraint = identity.authority == authority.key() @ IdentityError::Unauthorized
-    )]
-    pub identity: Account<'info, BusinessIdentity>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
-}
+    /// Propose a new program admin, who must accept to take over
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_keys_neq!(new_admin, config.admin, IdentityError::InvalidNewAuthority);
+        config.pending_admin = Some(new_admin);
 
-#[account]
-pub struct BusinessIdentity {
-    /// The wallet that owns this identity
-    pub authority: Pubkey,
-    /// Type of identity (1 = business)
-    pub identity_type: u8,
-    /// Business name (max 64 bytes)
-    pub name: String,
-    /// Logo URI on Arweave/Irys (max 200 bytes)
-    pub logo_uri: String,
-    /// Unix timestamp when created
-    pub created_at: i64,
-    /// Unix timestamp when last updated
-    pub updated_at: i64,
-    /// PDA bump seed
-    pub bump: u8,
-}
+        emit_cpi!(AdminProposed {
+            admin: config.admin,
+            pending_admin: new_admin,
+        });
 
-impl BusinessIdentity {
-    /// Calculate account size
-    /// 8 (discriminator) + 32 (authority) + 1 (identity_type) +
-    /// 4+64 (name string) + 4+200 (logo_uri string) + 8 (created_at) + 8 (updated_at) + 1 (bump)
-    pub const SIZE: usize = 8 + 32 + 1 + (4 + MAX_NAME_LENGTH) + (4 + MAX_LOGO_URI_LENGTH) + 8 + 8 + 1;
-}
+        verbose_msg!("Admin rotation proposed to: {}", new_admin);
 
-#[error_code]
-pub enum IdentityError {
-    #[msg("Name must be 1-64 characters")]
-    InvalidNameLength,
-    #[msg("Logo URI must be at most 200 characters")]
-    InvalidLogoUriLength,
-    #[msg("Only the identity owner can perform this action")]
-    Unauthorized,
+        Ok(())
+    }
+
+    /// Accept a pending admin rotation, signed by the proposed admin
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = ctx.accounts.new_admin.key();
+        config.pending_admin = None;
+
+        emit_cpi!(AdminChanged {
+            previous_admin,
+            new_admin: config.admin,
+        });
+
+        verbose_msg!("Admin rotated from {} to {}", previous_admin, config.admin);
+
+        Ok(())
+    }
+
+    /// Pause or resume groups of instructions
+    ///
+    /// `paused` is a combination of the `PAUSE_*` flags and replaces the
+    /// current value; pass 0 to resume everything.
+    pub fn set_paused(ctx: Context<SetPaused>, paused: u8) -> Result<()> {
+        require!(paused & !PAUSE_ALL == 0, IdentityError::InvalidPauseFlags);
+        ctx.accounts.config.paused = paused;
+
+        emit_cpi!(PausedChanged { paused });
+
+        verbose_msg!("Pause flags set to: {:#04b}", paused);
+
+        Ok(())
+    }
+
+    /// Set the bounty paid for closing an expired invoice
+    ///
+    /// The bounty is taken from the invoice's rent, so it never exceeds it.
+    pub fn set_crank_bounty(ctx: Context<SetCrankBounty>, lamports: u64) -> Result<()> {
+        ctx.accounts.config.crank_bounty = lamports;
+
+        emit_cpi!(CrankBountyChanged { lamports });
+
+        verbose_msg!("Crank bounty set to: {}", lamports);
+
+        Ok(())
+    }
+
+    /// Set the key that resolves disputes over escrowed payments
+    pub fn set_arbiter(ctx: Context<SetArbiter>, arbiter: Pubkey) -> Result<()> {
+        ctx.accounts.config.arbiter = arbiter;
+
+        emit_cpi!(ArbiterChanged { arbiter });
+
+        verbose_msg!("Arbiter set to: {}", arbiter);
+
+        Ok(())
+    }
+
+    /// Register an @handle for an identity that has none yet
+    ///
+    /// Handles are 3-20 characters of `a-z`, `0-9` and `_`, passed without
+    /// the leading `@`, and must not be reserved by the program admin.
+    pub fn register_handle(ctx: Context<RegisterHandle>, handle: String) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+
+        let handle_record = &mut ctx.accounts.handle_record;
+        handle_record.identity = identity.key();
+        handle_record.bump = ctx.bumps.handle_record;
+
+        identity.handle = Some(handle);
+        identity.updated_at = Clock::get()?.unix_timestamp;
+
+        let authority = ctx.accounts.authority.to_account_info();
+        resize_account(
+            &identity.to_account_info(),
+            identity.space(),
+            &authority,
+            &authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_cpi!(HandleChanged {
+            identity: identity.key(),
+            old_handle: None,
+            new_handle: identity.handle.clone(),
+        });
+
+        verbose_msg!(
+            "Handle registered: @{}",
+            identity.handle.as_deref().unwrap_or_default()
+        );
+
+        Ok(())
+    }
+
+    /// Replace the identity's @handle, releasing the old one
+    pub fn change_handle(ctx: Context<ChangeHandle>, new_handle: String) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+
+        let handle_record = &mut ctx.accounts.new_handle_record;
+        handle_record.identity = identity.key();
+        handle_record.bump = ctx.bumps.new_handle_record;
+
+        let old_handle = identity.handle.replace(new_handle);
+        identity.updated_at = Clock::get()?.unix_timestamp;
+
+        let authority = ctx.accounts.authority.to_account_info();
+        resize_account(
+            &identity.to_account_info(),
+            identity.space(),
+            &authority,
+            &authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_cpi!(HandleChanged {
+            identity: identity.key(),
+            old_handle,
+            new_handle: identity.handle.clone(),
+        });
+
+        verbose_msg!(
+            "Handle changed to: @{}",
+            identity.handle.as_deref().unwrap_or_default()
+        );
+
+        Ok(())
+    }
+
+    /// Release the identity's @handle so anyone can register it again
+    pub fn release_handle(ctx: Context<ReleaseHandle>) -> Result<()> {
+        let identity = &mut ctx.accounts.identity;
+        let old_handle = identity.handle.take();
+        identity.updated_at = Clock::get()?.unix_timestamp;
+
+        let authority = ctx.accounts.authority.to_account_info();
+        resize_account(
+            &identity.to_account_info(),
+            identity.space(),
+            &authority,
+            &authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_cpi!(HandleChanged {
+            identity: identity.key(),
+            old_handle,
+            new_handle: None,
+        });
+
+        verbose_msg!("Handle released");
+
+        Ok(())
+    }
+
+    /// Reserve a handle so that no identity can register it
+    ///
+    /// Only the program admin can reserve handles.
+    pub fn reserve_handle(ctx: Context<ReserveHandle>, handle: String) -> Result<()> {
+        ctx.accounts.reserved_handle.bump = ctx.bumps.reserved_handle;
+
+        emit_cpi!(HandleReservationChanged {
+            handle: handle.clone(),
+            reserved: true,
+        });
+
+        verbose_msg!("Handle reserved: @{}", handle);
+
+        Ok(())
+    }
+
+    /// Lift the reservation of a handle
+    pub fn unreserve_handle(ctx: Context<UnreserveHandle>, handle: String) -> Result<()> {
+        emit_cpi!(HandleReservationChanged {
+            handle: handle.clone(),
+            reserved: false,
+        });
+
+        verbose_msg!("Handle unreserved: @{}", handle);
+
+        Ok(())
+    }
+
+    /// Set the guardians who can recover the identity if its wallet is lost
+    ///
+    /// Only the identity authority can manage guardians. Approvals of a
+    /// recovery in progress only count for keys that are still guardians.
+    /// Guardians set by a previous authority can no longer recover the
+    /// identity once it changes hands.
+    pub fn set_guardians(
+        ctx: Context<SetGuardians>,
+        guardians: Vec<Pubkey>,
+        threshold: u8,
+        timelock: i64,
+    ) -> Result<()> {
+        let identity = &ctx.accounts.identity;
+        require!(
+            !guardians.is_empty()
+                && guardians.len() <= MAX_GUARDIANS
+                && !guardians.contains(&identity.authority)
+                && guardians
+                    .iter()
+                    .enumerate()
+                    .all(|(i, guardian)| !guardians[..i].contains(guardian)),
+            IdentityError::InvalidGuardians
+        );
+        require!(
+            threshold > 0 && threshold as usize <= guardians.len(),
+            IdentityError::InvalidGuardianThreshold
+        );
+        require!(
+            (MIN_RECOVERY_TIMELOCK..=MAX_RECOVERY_TIMELOCK).contains(&timelock),
+            IdentityError::InvalidRecoveryTimelock
+        );
+
+        let guardian_set = &mut ctx.accounts.guardian_set;
+        guardian_set.identity = identity.key();
+        guardian_set.authority = identity.authority;
+        guardian_set.guardians = guardians;
+        guardian_set.threshold = threshold;
+        guardian_set.timelock = timelock;
+        guardian_set.bump = ctx.bumps.guardian_set;
+
+        emit_cpi!(GuardiansUpdated {
+            identity: identity.key(),
+            guardians: guardian_set.guardians.clone(),
+            threshold,
+            timelock,
+        });
+
+        verbose_msg!("Guardians updated for: {}", identity.key());
+
+        Ok(())
+    }
+
+    /// Remove the guardian set, disabling recovery
+    pub fn remove_guardians(ctx: Context<RemoveGuardians>) -> Result<()> {
+        emit_cpi!(GuardiansUpdated {
+            identity: ctx.accounts.identity.key(),
+            guardians: Vec::new(),
+            threshold: 0,
+            timelock: 0,
+        });
+
+        verbose_msg!("Guardians removed for: {}", ctx.accounts.identity.key());
+
+        Ok(())
+    }
+
+    /// Start recovering the identity to `new_authority`
+    ///
+    /// Must be signed by a guardian, whose approval is counted.
+    pub fn initiate_recovery(ctx: Context<InitiateRecovery>, new_authority: Pubkey) -> Result<()> {
+        let identity = &ctx.accounts.identity;
+        require_keys_neq!(
+            new_authority,
+            identity.authority,
+            IdentityError::InvalidNewAuthority
+        );
+
+        let guardian_set = &ctx.accounts.guardian_set;
+        let recovery = &mut ctx.accounts.recovery;
+        recovery.identity = identity.key();
+        recovery.authority = identity.authority;
+        recovery.new_authority = new_authority;
+        recovery.initiator = ctx.accounts.guardian.key();
+        recovery.approvals = vec![ctx.accounts.guardian.key()];
+        recovery.unlocks_at = None;
+        recovery.bump = ctx.bumps.recovery;
+        recovery.start_timelock_if_approved(guardian_set, Clock::get()?.unix_timestamp);
+
+        emit_cpi!(RecoveryInitiated {
+            identity: identity.key(),
+            new_authority,
+            initiator: recovery.initiator,
+            unlocks_at: recovery.unlocks_at,
+        });
+
+        verbose_msg!("Recovery to {} initiated", new_authority);
+
+        Ok(())
+    }
+
+    /// Approve a recovery in progress as a guardian
+    ///
+    /// The timelock starts once enough guardians have approved.
+    pub fn approve_recovery(ctx: Context<ApproveRecovery>) -> Result<()> {
+        let guardian = ctx.accounts.guardian.key();
+        let recovery = &mut ctx.accounts.recovery;
+        require!(
+            !recovery.approvals.contains(&guardian),
+            IdentityError::AlreadyApproved
+        );
+        recovery
+            .approvals
+            .retain(|approver| ctx.accounts.guardian_set.guardians.contains(approver));
+        recovery.approvals.push(guardian);
+        recovery
+            .start_timelock_if_approved(&ctx.accounts.guardian_set, Clock::get()?.unix_timestamp);
+
+        emit_cpi!(RecoveryApproved {
+            identity: recovery.identity,
+            guardian,
+            approvals: recovery.approvals.len() as u8,
+            unlocks_at: recovery.unlocks_at,
+        });
+
+        verbose_msg!("Recovery approved by: {}", guardian);
+
+        Ok(())
+    }
+
+    /// Veto a recovery in progress
+    ///
+    /// Must be signed by the identity authority. Not affected by pausing,
+    /// so an owner can always stop a recovery of their wallet.
+    pub fn cancel_recovery(ctx: Context<CancelRecovery>) -> Result<()> {
+        let recovery = &ctx.accounts.recovery;
+
+        emit_cpi!(RecoveryCancelled {
+            identity: recovery.identity,
+            new_authority: recovery.new_authority,
+        });
+
+        verbose_msg!("Recovery to {} cancelled", recovery.new_authority);
+
+        Ok(())
+    }
+
+    /// Complete a recovery whose timelock has passed
+    ///
+    /// Must be signed by the new authority, which must not already own an
+    /// identity. The identity is re-pointed to it and any pending
+    /// authority transfer is dropped. The guardians keep protecting the
+    /// recovered identity.
+    pub fn complete_recovery(ctx: Context<CompleteRecovery>) -> Result<()> {
+        let clock = Clock::get()?;
+        let recovery = &ctx.accounts.recovery;
+        let approvals = recovery
+            .approvals
+            .iter()
+            .filter(|approver| ctx.accounts.guardian_set.guardians.contains(approver))
+            .count();
+        require!(
+            approvals >= ctx.accounts.guardian_set.threshold as usize,
+            IdentityError::ThresholdNotMet
+        );
+        require!(
+            recovery
+                .unlocks_at
+                .is_some_and(|unlocks_at| clock.unix_timestamp >= unlocks_at),
+            IdentityError::RecoveryTimelocked
+        );
+
+        let identity = &mut ctx.accounts.identity;
+        let previous_authority = identity.authority;
+        identity.authority = ctx.accounts.new_authority.key();
+        identity.pending_authority = None;
+        identity.updated_at = clock.unix_timestamp;
+        ctx.accounts.guardian_set.authority = identity.authority;
+
+        let pointer = &mut ctx.accounts.new_pointer;
+        pointer.identity = identity.key();
+        pointer.bump = ctx.bumps.new_pointer;
+
+        emit_cpi!(AuthorityTransferred {
+            identity: identity.key(),
+            previous_authority,
+            new_authority: identity.authority,
+        });
+
+        verbose_msg!(
+            "Identity recovered from {} to {}",
+            previous_authority,
+            identity.authority
+        );
+
+        Ok(())
+    }
+
+    /// Add a staff member who may act on the identity within `permissions`
+    ///
+    /// Signed by the identity authority or a staff member with
+    /// `ROLE_MANAGE_STAFF`, who can only grant permissions they hold.
+    pub fn add_staff(
+        ctx: Context<AddStaff>,
+        staff: Pubkey,
+        permissions: StaffPermissions,
+    ) -> Result<()> {
+        permissions.validate()?;
+        require_keys_neq!(
+            staff,
+            ctx.accounts.identity.authority,
+            IdentityError::InvalidStaff
+        );
+        check_staff_grant(
+            &ctx.accounts.identity,
+            &ctx.accounts.authority,
+            ctx.accounts.manager.as_deref(),
+            &[&permissions],
+        )?;
+
+        let member = &mut ctx.accounts.staff_member;
+        member.identity = ctx.accounts.identity.key();
+        member.authority = ctx.accounts.identity.authority;
+        member.staff = staff;
+        member.permissions = permissions;
+        member.created_at = Clock::get()?.unix_timestamp;
+        member.bump = ctx.bumps.staff_member;
+
+        emit_cpi!(StaffUpdated {
+            identity: member.identity,
+            staff,
+            permissions: member.permissions.clone(),
+        });
+
+        verbose_msg!("Staff member added: {}", staff);
+
+        Ok(())
+    }
+
+    /// Replace the permissions of a staff member
+    ///
+    /// Signed as in [`add_staff`]; a staff manager can only change staff
+    /// whose permissions they hold. Also re-grants a staff member added
+    /// under a previous authority.
+    pub fn update_staff(ctx: Context<UpdateStaff>, permissions: StaffPermissions) -> Result<()> {
+        permissions.validate()?;
+        check_staff_grant(
+            &ctx.accounts.identity,
+            &ctx.accounts.authority,
+            ctx.accounts.manager.as_deref(),
+            &[&permissions, &ctx.accounts.staff_member.permissions],
+        )?;
+
+        let member = &mut ctx.accounts.staff_member;
+        member.authority = ctx.accounts.identity.authority;
+        member.permissions = permissions;
+
+        emit_cpi!(StaffUpdated {
+            identity: member.identity,
+            staff: member.staff,
+            permissions: member.permissions.clone(),
+        });
+
+        verbose_msg!("Staff member updated: {}", member.staff);
+
+        Ok(())
+    }
+
+    /// Remove a staff member, returning its rent to the identity authority
+    ///
+    /// Signed as in [`update_staff`].
+    pub fn remove_staff(ctx: Context<RemoveStaff>) -> Result<()> {
+        let member = &ctx.accounts.staff_member;
+        check_staff_grant(
+            &ctx.accounts.identity,
+            &ctx.accounts.authority,
+            ctx.accounts.manager.as_deref(),
+            &[&member.permissions],
+        )?;
+
+        emit_cpi!(StaffRemoved {
+            identity: member.identity,
+            staff: member.staff,
+        });
+
+        verbose_msg!("Staff member removed: {}", member.staff);
+
+        Ok(())
+    }
+
+    /// Create an m-of-n multisig
+    ///
+    /// The multisig acts through its signer PDA, which can become the
+    /// authority of an identity via the regular transfer flow. Any
+    /// instruction signed by that authority then has to be proposed,
+    /// approved by `threshold` members and executed.
+    pub fn create_multisig(
+        ctx: Context<CreateMultisig>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        multisig.create_key = ctx.accounts.create_key.key();
+        multisig.bump = ctx.bumps.multisig;
+        let (signer, signer_bump) = Pubkey::find_program_address(
+            &[MULTISIG_SIGNER_SEED, multisig.key().as_ref()],
+            &crate::ID,
+        );
+        multisig.signer_bump = signer_bump;
+        multisig.set_members(members, threshold, signer)?;
+
+        emit_cpi!(MultisigUpdated {
+            multisig: multisig.key(),
+            signer,
+            members: multisig.members.clone(),
+            threshold,
+            generation: multisig.generation,
+        });
+
+        verbose_msg!("Multisig created with signer: {}", signer);
+
+        Ok(())
+    }
+
+    /// Replace the members and threshold of a multisig
+    ///
+    /// Must be signed by the multisig signer, i.e. executed through a
+    /// proposal. Proposals created before the change can no longer be
+    /// approved or executed.
+    pub fn set_multisig_members(
+        ctx: Context<SetMultisigMembers>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let signer = ctx.accounts.multisig_signer.key();
+        multisig.set_members(members, threshold, signer)?;
+        multisig.generation = multisig.generation.wrapping_add(1);
+
+        emit_cpi!(MultisigUpdated {
+            multisig: multisig.key(),
+            signer,
+            members: multisig.members.clone(),
+            threshold,
+            generation: multisig.generation,
+        });
+
+        verbose_msg!("Multisig members updated: {}", multisig.key());
+
+        Ok(())
+    }
+
+    /// Propose an instruction to be signed by the multisig signer
+    ///
+    /// The proposer must be a member and counts as the first approval.
+    pub fn create_proposal(
+        ctx: Context<CreateProposal>,
+        program_id: Pubkey,
+        accounts: Vec<ProposalAccount>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            program_id == crate::ID || program_id == System::id(),
+            IdentityError::UnsupportedProposalProgram
+        );
+        require!(
+            accounts.len() <= MAX_PROPOSAL_ACCOUNTS && data.len() <= MAX_PROPOSAL_DATA_LENGTH,
+            IdentityError::ProposalTooLarge
+        );
+
+        let multisig = &mut ctx.accounts.multisig;
+        let member = multisig.member_index(&ctx.accounts.proposer.key())?;
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.multisig = multisig.key();
+        proposal.index = multisig.proposal_count;
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.generation = multisig.generation;
+        proposal.program_id = program_id;
+        proposal.accounts = accounts;
+        proposal.data = data;
+        proposal.approvals = 1 << member;
+        proposal.created_at = Clock::get()?.unix_timestamp;
+        proposal.bump = ctx.bumps.proposal;
+        multisig.proposal_count += 1;
+
+        emit_cpi!(ProposalCreated {
+            multisig: multisig.key(),
+            proposal: proposal.key(),
+            index: proposal.index,
+            proposer: proposal.proposer,
+            program_id,
+        });
+
+        verbose_msg!("Proposal {} created", proposal.index);
+
+        Ok(())
+    }
+
+    /// Approve a proposal as a member of its multisig
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        let member = ctx
+            .accounts
+            .multisig
+            .member_index(&ctx.accounts.member.key())?;
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.approvals & (1 << member) == 0,
+            IdentityError::AlreadyApproved
+        );
+        proposal.approvals |= 1 << member;
+
+        emit_cpi!(ProposalApproved {
+            multisig: proposal.multisig,
+            proposal: proposal.key(),
+            member: ctx.accounts.member.key(),
+            approvals: proposal.approvals.count_ones() as u8,
+        });
+
+        verbose_msg!("Proposal {} approved", proposal.index);
+
+        Ok(())
+    }
+
+    /// Execute an approved proposal and close it
+    ///
+    /// The accounts of the proposed instruction, and the program it
+    /// targets, are passed as remaining accounts in the order they were
+    /// proposed.
+    pub fn execute_proposal<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteProposal<'info>>,
+    ) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        multisig.member_index(&ctx.accounts.member.key())?;
+        let proposal = &mut ctx.accounts.proposal;
+        require!(
+            proposal.approvals.count_ones() >= multisig.threshold as u32,
+            IdentityError::ThresholdNotMet
+        );
+        require!(!proposal.executed, IdentityError::ProposalAlreadyExecuted);
+
+        let remaining = ctx.remaining_accounts;
+        require!(
+            remaining.len() >= proposal.accounts.len(),
+            IdentityError::ProposalAccountMismatch
+        );
+        for (meta, info) in proposal.accounts.iter().zip(remaining) {
+            require_keys_eq!(
+                meta.pubkey,
+                info.key(),
+                IdentityError::ProposalAccountMismatch
+            );
+        }
+        let instruction = Instruction {
+            program_id: proposal.program_id,
+            accounts: proposal
+                .accounts
+                .iter()
+                .map(|meta| AccountMeta {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: proposal.data.clone(),
+        };
+
+        // Persist the flag before the call so the proposal cannot be
+        // executed again from within it.
+        proposal.executed = true;
+        proposal.exit(&crate::ID)?;
+
+        let multisig_key = multisig.key();
+        invoke_signed(
+            &instruction,
+            remaining,
+            &[&[
+                MULTISIG_SIGNER_SEED,
+                multisig_key.as_ref(),
+                &[multisig.signer_bump],
+            ]],
+        )?;
+
+        emit_cpi!(ProposalExecuted {
+            multisig: multisig_key,
+            proposal: proposal.key(),
+            index: proposal.index,
+            executor: ctx.accounts.member.key(),
+        });
+
+        verbose_msg!("Proposal {} executed", proposal.index);
+
+        Ok(())
+    }
+
+    /// Withdraw a proposal that has not been executed, returning its rent
+    pub fn cancel_proposal(ctx: Context<CancelProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+
+        emit_cpi!(ProposalCancelled {
+            multisig: proposal.multisig,
+            proposal: proposal.key(),
+            index: proposal.index,
+        });
+
+        verbose_msg!("Proposal {} cancelled", proposal.index);
+
+        Ok(())
+    }
+
+    /// Allowlist a verifier key that may issue attestations
+    ///
+    /// Only the program admin can add verifiers.
+    pub fn add_verifier(ctx: Context<AddVerifier>, verifier: Pubkey) -> Result<()> {
+        let record = &mut ctx.accounts.verifier_record;
+        record.verifier = verifier;
+        record.bump = ctx.bumps.verifier_record;
+
+        emit_cpi!(VerifierChanged {
+            verifier,
+            allowed: true,
+        });
+
+        verbose_msg!("Verifier added: {}", verifier);
+
+        Ok(())
+    }
+
+    /// Remove a verifier from the allowlist
+    ///
+    /// Attestations it issued stay on-chain, but clients must no longer
+    /// treat them as valid.
+    pub fn remove_verifier(ctx: Context<RemoveVerifier>) -> Result<()> {
+        emit_cpi!(VerifierChanged {
+            verifier: ctx.accounts.verifier_record.verifier,
+            allowed: false,
+        });
+
+        verbose_msg!(
+            "Verifier removed: {}",
+            ctx.accounts.verifier_record.verifier
+        );
+
+        Ok(())
+    }
+
+    /// Attest a claim about an identity
+    ///
+    /// Signed by an allowlisted verifier. Re-issuing the same claim
+    /// replaces the previous attestation, including a revoked one.
+    /// `claim_type` is the discriminant of a [`ClaimType`].
+    pub fn issue_attestation(
+        ctx: Context<IssueAttestation>,
+        claim_type: u8,
+        evidence_hash: [u8; 32],
+        expires_at: i64,
+    ) -> Result<()> {
+        ClaimType::try_from(claim_type)?;
+        let clock = Clock::get()?;
+        require!(
+            expires_at > clock.unix_timestamp,
+            IdentityError::InvalidAttestationExpiry
+        );
+
+        let attestation = &mut ctx.accounts.attestation;
+        attestation.identity = ctx.accounts.identity.key();
+        attestation.authority = ctx.accounts.identity.authority;
+        attestation.name_hash = normalized_name_hash(&ctx.accounts.identity.name);
+        attestation.identity_created_at = ctx.accounts.identity.created_at;
+        attestation.verifier = ctx.accounts.verifier.key();
+        attestation.claim_type = claim_type;
+        attestation.evidence_hash = evidence_hash;
+        attestation.issued_at = clock.unix_timestamp;
+        attestation.expires_at = expires_at;
+        attestation.revoked = false;
+        attestation.bump = ctx.bumps.attestation;
+
+        emit_cpi!(AttestationIssued {
+            attestation: attestation.key(),
+            identity: attestation.identity,
+            verifier: attestation.verifier,
+            claim_type,
+            expires_at,
+        });
+
+        verbose_msg!("Attestation issued for: {}", attestation.identity);
+
+        Ok(())
+    }
+
+    /// Revoke an attestation, signed by the verifier that issued it
+    pub fn revoke_attestation(ctx: Context<RevokeAttestation>) -> Result<()> {
+        let attestation = &mut ctx.accounts.attestation;
+        require!(!attestation.revoked, IdentityError::AttestationRevoked);
+        attestation.revoked = true;
+
+        emit_cpi!(AttestationRevoked {
+            attestation: attestation.key(),
+            identity: attestation.identity,
+            verifier: attestation.verifier,
+            claim_type: attestation.claim_type,
+        });
+
+        verbose_msg!("Attestation revoked for: {}", attestation.identity);
+
+        Ok(())
+    }
+
+    /// Remove an attestation from an identity, signed by its authority
+    ///
+    /// The rent goes back to the verifier that paid for it.
+    pub fn remove_attestation(ctx: Context<RemoveAttestation>) -> Result<()> {
+        let attestation = &ctx.accounts.attestation;
+
+        emit_cpi!(AttestationRemoved {
+            attestation: attestation.key(),
+            identity: attestation.identity,
+            verifier: attestation.verifier,
+            claim_type: attestation.claim_type,
+        });
+
+        verbose_msg!("Attestation removed from: {}", attestation.identity);
+
+        Ok(())
+    }
+
+    /// Upgrade an identity stored in an older layout to the current one
+    ///
+    /// The account is reallocated to fit the current layout and new fields
+    /// get their defaults. `payer` (the owner or anyone sponsoring them)
+    /// covers any extra rent and the pointer PDA and name record that
+    /// version 1 lacked; rent freed by shrinking goes back to the authority.
+    /// Fails if another identity registered the name in the meantime.
+    pub fn migrate_identity(ctx: Context<MigrateIdentity>) -> Result<()> {
+        let identity_info = ctx.accounts.identity.to_account_info();
+        let (from_version, identity) = {
+            let data = identity_info.try_borrow_data()?;
+            (
+                BusinessIdentity::stored_version(&data)?,
+                BusinessIdentity::try_deserialize_any_version(&data)?,
+            )
+        };
+        require_keys_eq!(
+            identity.authority,
+            ctx.accounts.authority.key(),
+            IdentityError::Unauthorized
+        );
+
+        resize_account(
+            &identity_info,
+            identity.space(),
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+        )?;
+        identity.try_serialize(&mut &mut identity_info.try_borrow_mut_data()?[..])?;
+
+        let pointer = &mut ctx.accounts.pointer;
+        pointer.identity = identity_info.key();
+        pointer.bump = ctx.bumps.pointer;
+
+        let name_record = &mut ctx.accounts.name_record;
+        name_record.identity = identity_info.key();
+        name_record.bump = ctx.bumps.name_record;
+
+        emit_cpi!(IdentityMigrated {
+            identity: identity_info.key(),
+            from_version,
+            to_version: IDENTITY_VERSION,
+        });
+
+        verbose_msg!(
+            "Business identity migrated from v{} to v{}",
+            from_version,
+            IDENTITY_VERSION
+        );
+
+        Ok(())
+    }
+
+    /// Create an invoice for `amount` base units of `mint`
+    ///
+    /// `reference` is the Solana Pay reference key and, with the identity,
+    /// addresses the invoice. Signed by the identity authority, a staff
+    /// member with `ROLE_CREATE_INVOICES` within their invoice limit, or
+    /// the device of a terminal of the identity. The identity, or the
+    /// store when given, must have a settlement destination for `mint`.
+    ///
+    /// With an `escrow_release_window`, the invoice is paid into an escrow
+    /// vault that the merchant can claim once the window has passed. This
+    /// needs an arbiter to be configured for disputes.
+    pub fn create_invoice(
+        ctx: Context<CreateInvoice>,
+        reference: Pubkey,
+        amount: u64,
+        expires_at: Option<i64>,
+        memo: String,
+        escrow_release_window: Option<i64>,
+    ) -> Result<()> {
+        require!(amount > 0, IdentityError::InvalidInvoiceAmount);
+        if let Some(window) = escrow_release_window {
+            require!(
+                (MIN_ESCROW_RELEASE_WINDOW..=MAX_ESCROW_RELEASE_WINDOW).contains(&window),
+                IdentityError::InvalidEscrowReleaseWindow
+            );
+            require!(
+                ctx.accounts.config.arbiter != Pubkey::default(),
+                IdentityError::NoArbiter
+            );
+        }
+        require!(
+            memo.len() <= MAX_INVOICE_MEMO_LENGTH,
+            IdentityError::InvoiceMemoTooLong
+        );
+        let clock = Clock::get()?;
+        if let Some(expires_at) = expires_at {
+            require!(
+                expires_at > clock.unix_timestamp,
+                IdentityError::InvalidInvoiceExpiry
+            );
+        }
+
+        let identity = &ctx.accounts.identity;
+        let creator = &ctx.accounts.creator;
+        let store = ctx.accounts.store.as_deref();
+        let store_key = ctx.accounts.store.as_ref().map(|store| store.key());
+        match ctx.accounts.terminal.as_mut() {
+            Some(terminal) => {
+                use_terminal(terminal, identity, creator)?;
+                require!(
+                    terminal.store.is_none() || terminal.store == store_key,
+                    IdentityError::TerminalStoreMismatch
+                );
+            }
+            None => {
+                let staff = ctx.accounts.staff.as_deref();
+                require!(
+                    is_authorized(identity, &creator.key(), staff, ROLE_CREATE_INVOICES)?,
+                    IdentityError::Unauthorized
+                );
+                if identity.authority != creator.key() {
+                    if let Some(staff) = staff {
+                        staff.check_invoice_amount(amount)?;
+                    }
+                }
+            }
+        }
+        let mint = ctx.accounts.mint.key();
+        require!(
+            identity.settlement_account(store, &mint).is_some(),
+            IdentityError::NoSettlementAccount
+        );
+
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.identity = identity.key();
+        invoice.reference = reference;
+        invoice.store = store_key;
+        invoice.terminal = ctx
+            .accounts
+            .terminal
+            .as_ref()
+            .map(|terminal| terminal.key());
+        invoice.creator = creator.key();
+        invoice.mint = mint;
+        invoice.amount = amount;
+        invoice.status = InvoiceStatus::Open as u8;
+        invoice.memo = memo;
+        invoice.created_at = clock.unix_timestamp;
+        invoice.expires_at = expires_at;
+        invoice.bump = ctx.bumps.invoice;
+        invoice.payer = None;
+        invoice.paid_slot = None;
+        invoice.refunded_amount = 0;
+        invoice.refund_count = 0;
+        invoice.escrow_release_window = escrow_release_window;
+        invoice.escrow_release_at = None;
+
+        emit_cpi!(InvoiceCreated {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            reference,
+            store: invoice.store,
+            terminal: invoice.terminal,
+            mint,
+            amount,
+            expires_at,
+            escrow_release_window,
+        });
+
+        verbose_msg!("Invoice created for {} of {}", amount, mint);
+
+        Ok(())
+    }
+
+    /// Cancel an open invoice so it can no longer be paid
+    ///
+    /// Signed by the identity authority or a staff member with
+    /// `ROLE_CREATE_INVOICES`. The account is kept so clients watching
+    /// the reference see the cancellation; `close_expired_invoice`
+    /// reclaims it.
+    pub fn cancel_invoice(ctx: Context<CancelInvoice>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.status = InvoiceStatus::Cancelled as u8;
+
+        emit_cpi!(InvoiceCancelled {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            reference: invoice.reference,
+        });
+
+        verbose_msg!("Invoice cancelled: {}", invoice.reference);
+
+        Ok(())
+    }
+
+    /// Close an invoice that can no longer be paid
+    ///
+    /// Permissionless: anyone may close an open invoice past its expiry
+    /// or a cancelled one. The caller receives the configured bounty out
+    /// of the rent and the rest returns to the invoice creator.
+    pub fn close_expired_invoice(ctx: Context<CloseExpiredInvoice>) -> Result<()> {
+        let invoice = &ctx.accounts.invoice;
+        let status = InvoiceStatus::try_from(invoice.status)?;
+        let now = Clock::get()?.unix_timestamp;
+        let expired = invoice
+            .expires_at
+            .is_some_and(|expires_at| now >= expires_at);
+        require!(
+            status == InvoiceStatus::Cancelled || (status == InvoiceStatus::Open && expired),
+            IdentityError::InvoiceNotExpired
+        );
+
+        let invoice_info = invoice.to_account_info();
+        let bounty = ctx
+            .accounts
+            .config
+            .crank_bounty
+            .min(invoice_info.lamports());
+        **invoice_info.try_borrow_mut_lamports()? -= bounty;
+        **ctx.accounts.caller.try_borrow_mut_lamports()? += bounty;
+
+        emit_cpi!(InvoiceClosed {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            reference: invoice.reference,
+            caller: ctx.accounts.caller.key(),
+            bounty,
+        });
+
+        verbose_msg!("Invoice closed: {}", invoice.reference);
+
+        Ok(())
+    }
+
+    /// Pay an open invoice in full
+    ///
+    /// Transfers exactly the invoice amount from the payer's token account
+    /// to the settlement destination of the store or identity, and marks
+    /// the invoice paid in the same instruction.
+    pub fn pay_invoice(ctx: Context<PayInvoice>) -> Result<()> {
+        let clock = Clock::get()?;
+        let invoice = &mut ctx.accounts.invoice;
+        require!(
+            invoice
+                .expires_at
+                .map_or(true, |expires_at| clock.unix_timestamp < expires_at),
+            IdentityError::InvoiceExpired
+        );
+
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.settlement_token_account.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            invoice.amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        invoice.status = InvoiceStatus::Paid as u8;
+        invoice.payer = Some(ctx.accounts.payer.key());
+        invoice.paid_slot = Some(clock.slot);
+
+        emit_cpi!(InvoicePaid {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            reference: invoice.reference,
+            payer: ctx.accounts.payer.key(),
+            mint: invoice.mint,
+            amount: invoice.amount,
+            destination: ctx.accounts.settlement_token_account.key(),
+            slot: clock.slot,
+        });
+
+        verbose_msg!("Invoice paid by: {}", ctx.accounts.payer.key());
+
+        Ok(())
+    }
+
+    /// Refund part or all of a paid invoice to its payer
+    ///
+    /// Signed by the identity authority or a staff member with
+    /// `ROLE_ISSUE_REFUNDS` within their refund limit, who must own or be
+    /// a delegate of `source_token_account`. A staff limit caps the total
+    /// refunded on the invoice, including earlier refunds. Refunds are capped at the
+    /// amount paid minus earlier refunds; each is recorded in a
+    /// [`Refund`] account.
+    pub fn refund_invoice(ctx: Context<RefundInvoice>, amount: u64, reason: u8) -> Result<()> {
+        require!(
+            RefundReason::try_from(reason)? != RefundReason::DisputeResolution,
+            IdentityError::InvalidRefundReason
+        );
+        require!(amount > 0, IdentityError::InvalidRefundAmount);
+        let invoice = &mut ctx.accounts.invoice;
+        require!(
+            amount <= invoice.amount - invoice.refunded_amount,
+            IdentityError::RefundExceedsPaidAmount
+        );
+        if ctx.accounts.identity.authority != ctx.accounts.authority.key() {
+            if let Some(staff) = ctx.accounts.staff.as_deref() {
+                staff.check_refund_amount(invoice.refunded_amount + amount)?;
+            }
+        }
+
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.source_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.payer_token_account.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let refund = &mut ctx.accounts.refund;
+        refund.invoice = invoice.key();
+        refund.index = invoice.refund_count;
+        refund.amount = amount;
+        refund.reason = reason;
+        refund.refunded_by = ctx.accounts.authority.key();
+        refund.created_at = Clock::get()?.unix_timestamp;
+        refund.bump = ctx.bumps.refund;
+
+        invoice.refunded_amount += amount;
+        invoice.refund_count += 1;
+        if invoice.refunded_amount == invoice.amount {
+            invoice.status = InvoiceStatus::Refunded as u8;
+        }
+
+        emit_cpi!(InvoiceRefunded {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            refund: refund.key(),
+            payer_token_account: ctx.accounts.payer_token_account.key(),
+            amount,
+            reason,
+            refunded_amount: invoice.refunded_amount,
+            refunded_by: refund.refunded_by,
+        });
+
+        verbose_msg!("Invoice refunded {} of {}", amount, invoice.amount);
+
+        Ok(())
+    }
+
+    /// Pay an open escrowed invoice in full into its escrow vault
+    ///
+    /// Refused while no arbiter is configured, since the payment could not
+    /// be disputed. The payer funds the vault's rent and gets it back when
+    /// the escrow is settled. They can dispute the payment until the
+    /// release window has passed.
+    pub fn pay_invoice_escrow(ctx: Context<PayInvoiceEscrow>) -> Result<()> {
+        let clock = Clock::get()?;
+        let invoice = &mut ctx.accounts.invoice;
+        require!(
+            invoice
+                .expires_at
+                .map_or(true, |expires_at| clock.unix_timestamp < expires_at),
+            IdentityError::InvoiceExpired
+        );
+        let window = invoice
+            .escrow_release_window
+            .ok_or(IdentityError::NotEscrowInvoice)?;
+
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            invoice.amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        invoice.status = InvoiceStatus::Escrowed as u8;
+        invoice.payer = Some(ctx.accounts.payer.key());
+        ctx.accounts.identity.open_escrows += 1;
+        if let Some(store) = ctx.accounts.store.as_mut() {
+            store.open_escrows += 1;
+        }
+        invoice.paid_slot = Some(clock.slot);
+        invoice.escrow_release_at = Some(clock.unix_timestamp.saturating_add(window));
+
+        emit_cpi!(InvoicePaid {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            reference: invoice.reference,
+            payer: ctx.accounts.payer.key(),
+            mint: invoice.mint,
+            amount: invoice.amount,
+            destination: ctx.accounts.vault.key(),
+            slot: clock.slot,
+        });
+
+        verbose_msg!("Invoice paid into escrow by: {}", ctx.accounts.payer.key());
+
+        Ok(())
+    }
+
+    /// Release an undisputed escrow to the merchant once its window has
+    /// passed
+    ///
+    /// Permissionless, since the funds can only go to the settlement
+    /// destination of the store or identity.
+    pub fn claim_escrow(ctx: Context<ClaimEscrow>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            invoice
+                .escrow_release_at
+                .is_some_and(|release_at| now >= release_at),
+            IdentityError::EscrowNotReleased
+        );
+
+        let accounts = EscrowAccounts {
+            invoice,
+            vault: &ctx.accounts.vault,
+            mint: &ctx.accounts.mint,
+            token_program: &ctx.accounts.token_program,
+        };
+        accounts.release(&ctx.accounts.settlement_token_account, invoice.amount)?;
+        accounts.close_vault(&ctx.accounts.payer)?;
+        invoice.status = InvoiceStatus::Paid as u8;
+        ctx.accounts.identity.open_escrows -= 1;
+        if let Some(store) = ctx.accounts.store.as_mut() {
+            store.open_escrows -= 1;
+        }
+
+        emit_cpi!(EscrowReleased {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            merchant_amount: invoice.amount,
+            payer_amount: 0,
+        });
+
+        verbose_msg!("Escrow released for: {}", invoice.reference);
+
+        Ok(())
+    }
+
+    /// Dispute an escrowed payment before its release window has passed
+    ///
+    /// Must be signed by the payer. The funds stay in escrow until the
+    /// arbiter resolves the dispute.
+    pub fn open_dispute(ctx: Context<OpenDispute>) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            invoice
+                .escrow_release_at
+                .is_some_and(|release_at| now < release_at),
+            IdentityError::EscrowReleased
+        );
+        invoice.status = InvoiceStatus::Disputed as u8;
+
+        emit_cpi!(DisputeOpened {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            payer: ctx.accounts.payer.key(),
+        });
+
+        verbose_msg!("Dispute opened for: {}", invoice.reference);
+
+        Ok(())
+    }
+
+    /// Resolve a dispute by splitting the escrow between payer and merchant
+    ///
+    /// Must be signed by the arbiter in the program config. `payer_amount`
+    /// goes back to the payer and is recorded as refunded, in a [`Refund`]
+    /// account paid for by the arbiter; the rest goes to the merchant's
+    /// settlement destination.
+    pub fn resolve_dispute(ctx: Context<ResolveDispute>, payer_amount: u64) -> Result<()> {
+        let invoice = &mut ctx.accounts.invoice;
+        require!(
+            payer_amount <= invoice.amount,
+            IdentityError::InvalidDisputeSplit
+        );
+        let merchant_amount = invoice.amount - payer_amount;
+
+        let accounts = EscrowAccounts {
+            invoice,
+            vault: &ctx.accounts.vault,
+            mint: &ctx.accounts.mint,
+            token_program: &ctx.accounts.token_program,
+        };
+        accounts.release(&ctx.accounts.payer_token_account, payer_amount)?;
+        accounts.release(&ctx.accounts.settlement_token_account, merchant_amount)?;
+        accounts.close_vault(&ctx.accounts.payer)?;
+
+        match ctx.accounts.refund.as_mut() {
+            Some(refund) => {
+                require!(payer_amount > 0, IdentityError::InvalidRefundAmount);
+                refund.invoice = invoice.key();
+                refund.index = invoice.refund_count;
+                refund.amount = payer_amount;
+                refund.reason = RefundReason::DisputeResolution as u8;
+                refund.refunded_by = ctx.accounts.arbiter.key();
+                refund.created_at = Clock::get()?.unix_timestamp;
+                refund.bump = ctx.bumps.refund.unwrap_or_default();
+                invoice.refund_count += 1;
+
+                emit_cpi!(InvoiceRefunded {
+                    identity: invoice.identity,
+                    invoice: invoice.key(),
+                    refund: refund.key(),
+                    payer_token_account: ctx.accounts.payer_token_account.key(),
+                    amount: payer_amount,
+                    reason: refund.reason,
+                    refunded_amount: payer_amount,
+                    refunded_by: refund.refunded_by,
+                });
+            }
+            None => require!(payer_amount == 0, IdentityError::RefundRecordRequired),
+        }
+        invoice.refunded_amount = payer_amount;
+        invoice.status = if payer_amount == invoice.amount {
+            InvoiceStatus::Refunded as u8
+        } else {
+            InvoiceStatus::Paid as u8
+        };
+        ctx.accounts.identity.open_escrows -= 1;
+        if let Some(store) = ctx.accounts.store.as_mut() {
+            store.open_escrows -= 1;
+        }
+
+        emit_cpi!(EscrowReleased {
+            identity: invoice.identity,
+            invoice: invoice.key(),
+            merchant_amount,
+            payer_amount,
+        });
+
+        verbose_msg!(
+            "Dispute resolved: {} to payer, {} to merchant",
+            payer_amount,
+            merchant_amount
+        );
+
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    identity_type: u8,
+    name: String,
+    logo_uri: String,
+    logo_sha256: Option<[u8; 32]>,
+    logo_mime: Option<String>
+)]
+pub struct CreateIdentity<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_CREATE) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = BusinessIdentity::BASE_SIZE
+            + name.len()
+            + logo_uri.len()
+            + logo_mime.as_ref().map_or(0, |mime| 4 + mime.len()),
+        seeds = [IDENTITY_SEED, create_key.key().as_ref()],
+        bump
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Lets the wallet find its identity from its own address.
+    /// Its existence also enforces one identity per wallet.
+    #[account(
+        init,
+        payer = authority,
+        space = IdentityPointer::SIZE,
+        seeds = [IDENTITY_POINTER_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub pointer: Account<'info, IdentityPointer>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = NameRecord::SIZE,
+        seeds = [NAME_RECORD_SEED, normalized_name_hash(&name).as_ref()],
+        bump,
+        constraint = name_record.identity == Pubkey::default() @ IdentityError::NameTaken
+    )]
+    pub name_record: Account<'info, NameRecord>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Fresh keypair used once as the identity PDA seed
+    pub create_key: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(name: Option<String>)]
+pub struct UpdateIdentity<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = is_authorized(&identity, &authority.key(), staff.as_deref(), ROLE_EDIT_PROFILE)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub staff: Option<Account<'info, StaffMember>>,
+
+    /// CHECK: Registry record of the stored name, released on rename if
+    /// it is held by this identity
+    #[account(
+        mut,
+        seeds = [NAME_RECORD_SEED, normalized_name_hash(&identity.name).as_ref()],
+        bump
+    )]
+    pub current_name_record: UncheckedAccount<'info>,
+
+    /// Registry record of the new name, required when renaming
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = NameRecord::SIZE,
+        seeds = [
+            NAME_RECORD_SEED,
+            normalized_name_hash(name.as_deref().unwrap_or_default()).as_ref()
+        ],
+        bump,
+        constraint = new_name_record.identity == Pubkey::default()
+            || new_name_record.identity == identity.key() @ IdentityError::NameTaken
+    )]
+    pub new_name_record: Option<Account<'info, NameRecord>>,
+
+    /// CHECK: The identity authority, who receives freed rent
+    #[account(mut, address = identity.authority @ IdentityError::Unauthorized)]
+    pub rent_destination: UncheckedAccount<'info>,
+
+    /// The identity authority, or a staff member with `ROLE_EDIT_PROFILE`
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CloseIdentity<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        close = destination,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.open_stores == 0 @ IdentityError::IdentityHasStores,
+        constraint = identity.open_escrows == 0 @ IdentityError::OpenEscrows
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = destination,
+        seeds = [IDENTITY_POINTER_SEED, authority.key().as_ref()],
+        bump = pointer.bump
+    )]
+    pub pointer: Account<'info, IdentityPointer>,
+
+    /// CHECK: Registry record of the name, released if held by this identity
+    #[account(
+        mut,
+        seeds = [NAME_RECORD_SEED, normalized_name_hash(&identity.name).as_ref()],
+        bump
+    )]
+    pub name_record: UncheckedAccount<'info>,
+
+    /// Record of the identity's @handle, required if it has one
+    #[account(
+        mut,
+        close = destination,
+        seeds = [
+            HANDLE_RECORD_SEED,
+            identity.handle.as_deref().unwrap_or_default().as_bytes()
+        ],
+        bump = handle_record.bump,
+        has_one = identity
+    )]
+    pub handle_record: Option<Account<'info, HandleRecord>>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: Any account may receive the reclaimed rent
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetSettlement<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateStore<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.kind()?.is_merchant() @ IdentityError::NotAMerchant
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Store::SIZE,
+        seeds = [
+            STORE_SEED,
+            identity.key().as_ref(),
+            identity.store_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub store: Account<'info, Store>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateStore<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = is_authorized(&identity, &authority.key(), staff.as_deref(), ROLE_EDIT_PROFILE)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub staff: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        mut,
+        seeds = [STORE_SEED, identity.key().as_ref(), store.index.to_le_bytes().as_ref()],
+        bump = store.bump,
+        has_one = identity
+    )]
+    pub store: Account<'info, Store>,
+
+    /// The identity authority, or a staff member with `ROLE_EDIT_PROFILE`
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetStoreSettlement<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        seeds = [STORE_SEED, identity.key().as_ref(), store.index.to_le_bytes().as_ref()],
+        bump = store.bump,
+        has_one = identity
+    )]
+    pub store: Account<'info, Store>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct DeactivateStore<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        seeds = [STORE_SEED, identity.key().as_ref(), store.index.to_le_bytes().as_ref()],
+        bump = store.bump,
+        has_one = identity,
+        constraint = store.active @ IdentityError::StoreInactive
+    )]
+    pub store: Account<'info, Store>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CloseStore<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [STORE_SEED, identity.key().as_ref(), store.index.to_le_bytes().as_ref()],
+        bump = store.bump,
+        has_one = identity,
+        constraint = !store.active @ IdentityError::StoreActive,
+        constraint = store.open_escrows == 0 @ IdentityError::OpenEscrows
+    )]
+    pub store: Account<'info, Store>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(device: Pubkey)]
+pub struct RegisterTerminal<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.kind()?.is_merchant() @ IdentityError::NotAMerchant
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Store the terminal is used at, if any
+    #[account(
+        has_one = identity,
+        constraint = store.active @ IdentityError::StoreInactive
+    )]
+    pub store: Option<Account<'info, Store>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = Terminal::SIZE,
+        seeds = [TERMINAL_SEED, identity.key().as_ref(), device.as_ref()],
+        bump,
+        constraint = !terminal.is_live(&identity) @ IdentityError::TerminalExists
+    )]
+    pub terminal: Account<'info, Terminal>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RevokeTerminal<'info> {
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        seeds = [TERMINAL_SEED, identity.key().as_ref(), terminal.device.as_ref()],
+        bump = terminal.bump,
+        has_one = identity,
+        constraint = !terminal.revoked @ IdentityError::TerminalRevoked
+    )]
+    pub terminal: Account<'info, Terminal>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CloseTerminal<'info> {
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [TERMINAL_SEED, identity.key().as_ref(), terminal.device.as_ref()],
+        bump = terminal.bump,
+        has_one = identity
+    )]
+    pub terminal: Account<'info, Terminal>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PingTerminal<'info> {
+    #[account(
+        mut,
+        seeds = [TERMINAL_SEED, terminal.identity.as_ref(), device.key().as_ref()],
+        bump = terminal.bump,
+        has_one = device @ IdentityError::Unauthorized,
+        constraint = !terminal.revoked @ IdentityError::TerminalRevoked
+    )]
+    pub terminal: Account<'info, Terminal>,
+
+    pub device: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ProposeAuthorityTransfer<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelAuthorityTransfer<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AcceptAuthorityTransfer<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.pending_authority == Some(new_authority.key())
+            @ IdentityError::PendingAuthorityMismatch,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = previous_authority,
+        seeds = [IDENTITY_POINTER_SEED, identity.authority.as_ref()],
+        bump = previous_pointer.bump
+    )]
+    pub previous_pointer: Account<'info, IdentityPointer>,
+
+    /// CHECK: Receives the rent of the previous pointer
+    #[account(mut, address = identity.authority)]
+    pub previous_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = new_authority,
+        space = IdentityPointer::SIZE,
+        seeds = [IDENTITY_POINTER_SEED, new_authority.key().as_ref()],
+        bump
+    )]
+    pub new_pointer: Account<'info, IdentityPointer>,
+
+    #[account(mut)]
+    pub new_authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = ProgramConfig::SIZE,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// Data account of this program
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = anchor_lang::solana_program::bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(payer.key())
+            @ IdentityError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.pending_admin == Some(new_admin.key())
+            @ IdentityError::PendingAuthorityMismatch
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub new_admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetCrankBounty<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetArbiter<'info> {
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(handle: String)]
+pub struct RegisterHandle<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.handle.is_none() @ IdentityError::HandleAlreadySet,
+        constraint = is_valid_handle(&handle) @ IdentityError::InvalidHandle
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = HandleRecord::SIZE,
+        seeds = [HANDLE_RECORD_SEED, handle.as_bytes()],
+        bump,
+        constraint = handle_record.identity == Pubkey::default() @ IdentityError::HandleTaken
+    )]
+    pub handle_record: Account<'info, HandleRecord>,
+
+    /// CHECK: Must not exist, i.e. the handle is not reserved
+    #[account(
+        seeds = [RESERVED_HANDLE_SEED, handle.as_bytes()],
+        bump,
+        constraint = reserved_handle.data_is_empty() @ IdentityError::HandleReserved
+    )]
+    pub reserved_handle: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(new_handle: String)]
+pub struct ChangeHandle<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.handle.is_some() @ IdentityError::NoHandle,
+        constraint = is_valid_handle(&new_handle) @ IdentityError::InvalidHandle
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [
+            HANDLE_RECORD_SEED,
+            identity.handle.as_deref().unwrap_or_default().as_bytes()
+        ],
+        bump = current_handle_record.bump,
+        has_one = identity
+    )]
+    pub current_handle_record: Account<'info, HandleRecord>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = HandleRecord::SIZE,
+        seeds = [HANDLE_RECORD_SEED, new_handle.as_bytes()],
+        bump,
+        constraint = new_handle_record.identity == Pubkey::default() @ IdentityError::HandleTaken
+    )]
+    pub new_handle_record: Account<'info, HandleRecord>,
+
+    /// CHECK: Must not exist, i.e. the new handle is not reserved
+    #[account(
+        seeds = [RESERVED_HANDLE_SEED, new_handle.as_bytes()],
+        bump,
+        constraint = reserved_handle.data_is_empty() @ IdentityError::HandleReserved
+    )]
+    pub reserved_handle: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ReleaseHandle<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.handle.is_some() @ IdentityError::NoHandle
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [
+            HANDLE_RECORD_SEED,
+            identity.handle.as_deref().unwrap_or_default().as_bytes()
+        ],
+        bump = handle_record.bump,
+        has_one = identity
+    )]
+    pub handle_record: Account<'info, HandleRecord>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(handle: String)]
+pub struct ReserveHandle<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = ReservedHandle::SIZE,
+        seeds = [RESERVED_HANDLE_SEED, handle.as_bytes()],
+        bump,
+        constraint = is_valid_handle(&handle) @ IdentityError::InvalidHandle
+    )]
+    pub reserved_handle: Account<'info, ReservedHandle>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(handle: String)]
+pub struct UnreserveHandle<'info> {
+    #[account(
+        mut,
+        close = admin,
+        seeds = [RESERVED_HANDLE_SEED, handle.as_bytes()],
+        bump = reserved_handle.bump
+    )]
+    pub reserved_handle: Account<'info, ReservedHandle>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetGuardians<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = GuardianSet::SIZE,
+        seeds = [GUARDIAN_SET_SEED, identity.key().as_ref()],
+        bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RemoveGuardians<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [GUARDIAN_SET_SEED, identity.key().as_ref()],
+        bump = guardian_set.bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitiateRecovery<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        seeds = [GUARDIAN_SET_SEED, identity.key().as_ref()],
+        bump = guardian_set.bump,
+        constraint = guardian_set.authority == identity.authority @ IdentityError::StaleGuardians,
+        constraint = guardian_set.guardians.contains(&guardian.key()) @ IdentityError::NotGuardian
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(
+        init,
+        payer = guardian,
+        space = Recovery::SIZE,
+        seeds = [RECOVERY_SEED, identity.key().as_ref()],
+        bump
+    )]
+    pub recovery: Account<'info, Recovery>,
+
+    #[account(mut)]
+    pub guardian: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ApproveRecovery<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [GUARDIAN_SET_SEED, recovery.identity.as_ref()],
+        bump = guardian_set.bump,
+        constraint = guardian_set.guardians.contains(&guardian.key()) @ IdentityError::NotGuardian
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(
+        mut,
+        seeds = [RECOVERY_SEED, recovery.identity.as_ref()],
+        bump = recovery.bump
+    )]
+    pub recovery: Account<'info, Recovery>,
+
+    pub guardian: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelRecovery<'info> {
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = initiator,
+        seeds = [RECOVERY_SEED, identity.key().as_ref()],
+        bump = recovery.bump,
+        has_one = initiator
+    )]
+    pub recovery: Account<'info, Recovery>,
+
+    /// CHECK: Guardian who started the recovery and receives its rent
+    #[account(mut)]
+    pub initiator: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CompleteRecovery<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        seeds = [GUARDIAN_SET_SEED, identity.key().as_ref()],
+        bump = guardian_set.bump,
+        constraint = guardian_set.authority == identity.authority @ IdentityError::StaleGuardians
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(
+        mut,
+        close = initiator,
+        seeds = [RECOVERY_SEED, identity.key().as_ref()],
+        bump = recovery.bump,
+        has_one = initiator,
+        has_one = new_authority @ IdentityError::PendingAuthorityMismatch,
+        constraint = recovery.authority == identity.authority @ IdentityError::StaleGuardians
+    )]
+    pub recovery: Account<'info, Recovery>,
+
+    /// CHECK: Guardian who started the recovery and receives its rent
+    #[account(mut)]
+    pub initiator: UncheckedAccount<'info>,
+
+    /// The lost wallet's pointer; its rent goes to the new authority
+    #[account(
+        mut,
+        close = new_authority,
+        seeds = [IDENTITY_POINTER_SEED, identity.authority.as_ref()],
+        bump = previous_pointer.bump
+    )]
+    pub previous_pointer: Account<'info, IdentityPointer>,
+
+    #[account(
+        init,
+        payer = new_authority,
+        space = IdentityPointer::SIZE,
+        seeds = [IDENTITY_POINTER_SEED, new_authority.key().as_ref()],
+        bump
+    )]
+    pub new_pointer: Account<'info, IdentityPointer>,
+
+    #[account(mut)]
+    pub new_authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(staff: Pubkey)]
+pub struct AddStaff<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.kind()?.is_merchant() @ IdentityError::NotAMerchant,
+        constraint = is_authorized(&identity, &authority.key(), manager.as_deref(), ROLE_MANAGE_STAFF)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub manager: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = StaffMember::SIZE,
+        seeds = [STAFF_SEED, identity.key().as_ref(), staff.as_ref()],
+        bump
+    )]
+    pub staff_member: Account<'info, StaffMember>,
+
+    /// The identity authority, or a staff member with `ROLE_MANAGE_STAFF`
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateStaff<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = is_authorized(&identity, &authority.key(), manager.as_deref(), ROLE_MANAGE_STAFF)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub manager: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        mut,
+        seeds = [STAFF_SEED, identity.key().as_ref(), staff_member.staff.as_ref()],
+        bump = staff_member.bump,
+        has_one = identity
+    )]
+    pub staff_member: Account<'info, StaffMember>,
+
+    /// The identity authority, or a staff member with `ROLE_MANAGE_STAFF`
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RemoveStaff<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = is_authorized(&identity, &authority.key(), manager.as_deref(), ROLE_MANAGE_STAFF)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub manager: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        mut,
+        close = rent_destination,
+        seeds = [STAFF_SEED, identity.key().as_ref(), staff_member.staff.as_ref()],
+        bump = staff_member.bump,
+        has_one = identity
+    )]
+    pub staff_member: Account<'info, StaffMember>,
+
+    /// CHECK: The identity authority, who receives the rent
+    #[account(mut, address = identity.authority @ IdentityError::Unauthorized)]
+    pub rent_destination: UncheckedAccount<'info>,
+
+    /// The identity authority, or a staff member with `ROLE_MANAGE_STAFF`
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateMultisig<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = Multisig::SIZE,
+        seeds = [MULTISIG_SEED, create_key.key().as_ref()],
+        bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+
+    /// Unique key the multisig address is derived from
+    pub create_key: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetMultisigMembers<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [MULTISIG_SEED, multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        seeds = [MULTISIG_SIGNER_SEED, multisig.key().as_ref()],
+        bump = multisig.signer_bump
+    )]
+    pub multisig_signer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [MULTISIG_SEED, multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = Proposal::SIZE,
+        seeds = [
+            PROPOSAL_SEED,
+            multisig.key().as_ref(),
+            multisig.proposal_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [MULTISIG_SEED, multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        mut,
+        seeds = [
+            PROPOSAL_SEED,
+            multisig.key().as_ref(),
+            proposal.index.to_le_bytes().as_ref()
+        ],
+        bump = proposal.bump,
+        has_one = multisig,
+        constraint = proposal.generation == multisig.generation @ IdentityError::ProposalStale
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    pub member: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [MULTISIG_SEED, multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [
+            PROPOSAL_SEED,
+            multisig.key().as_ref(),
+            proposal.index.to_le_bytes().as_ref()
+        ],
+        bump = proposal.bump,
+        has_one = multisig,
+        has_one = proposer,
+        constraint = proposal.generation == multisig.generation @ IdentityError::ProposalStale
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    /// CHECK: Receives the rent of the proposal
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    pub member: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        close = proposer,
+        has_one = proposer @ IdentityError::Unauthorized,
+        constraint = !proposal.executed @ IdentityError::ProposalAlreadyExecuted
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(verifier: Pubkey)]
+pub struct AddVerifier<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = VerifierRecord::SIZE,
+        seeds = [VERIFIER_SEED, verifier.as_ref()],
+        bump
+    )]
+    pub verifier_record: Account<'info, VerifierRecord>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RemoveVerifier<'info> {
+    #[account(
+        mut,
+        close = admin,
+        seeds = [VERIFIER_SEED, verifier_record.verifier.as_ref()],
+        bump = verifier_record.bump
+    )]
+    pub verifier_record: Account<'info, VerifierRecord>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ IdentityError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(claim_type: u8)]
+pub struct IssueAttestation<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        init_if_needed,
+        payer = verifier,
+        space = Attestation::SIZE,
+        seeds = [
+            ATTESTATION_SEED,
+            identity.key().as_ref(),
+            verifier.key().as_ref(),
+            &[claim_type]
+        ],
+        bump
+    )]
+    pub attestation: Account<'info, Attestation>,
+
+    #[account(
+        seeds = [VERIFIER_SEED, verifier.key().as_ref()],
+        bump = verifier_record.bump
+    )]
+    pub verifier_record: Account<'info, VerifierRecord>,
+
+    #[account(mut)]
+    pub verifier: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RevokeAttestation<'info> {
+    #[account(
+        mut,
+        seeds = [
+            ATTESTATION_SEED,
+            attestation.identity.as_ref(),
+            verifier.key().as_ref(),
+            &[attestation.claim_type]
+        ],
+        bump = attestation.bump,
+        has_one = verifier @ IdentityError::Unauthorized
+    )]
+    pub attestation: Account<'info, Attestation>,
+
+    pub verifier: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RemoveAttestation<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        has_one = authority @ IdentityError::Unauthorized,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        close = verifier,
+        seeds = [
+            ATTESTATION_SEED,
+            identity.key().as_ref(),
+            verifier.key().as_ref(),
+            &[attestation.claim_type]
+        ],
+        bump = attestation.bump,
+        has_one = identity,
+        has_one = verifier
+    )]
+    pub attestation: Account<'info, Attestation>,
+
+    /// CHECK: Receives the attestation rent, checked against the attestation
+    #[account(mut)]
+    pub verifier: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigrateIdentity<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_UPDATES) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    /// CHECK: Decoded by layout version in the handler
+    #[account(
+        mut,
+        owner = crate::ID,
+        constraint = BusinessIdentity::stored_version(&identity.try_borrow_data()?)?
+            < IDENTITY_VERSION @ IdentityError::AlreadyMigrated
+    )]
+    pub identity: UncheckedAccount<'info>,
+
+    /// Created for identities from before pointers existed. Must not point
+    /// at another identity the wallet created since.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = IdentityPointer::SIZE,
+        seeds = [IDENTITY_POINTER_SEED, authority.key().as_ref()],
+        bump,
+        constraint = pointer.is_free_for(&identity.key()) @ IdentityError::WalletHasIdentity
+    )]
+    pub pointer: Account<'info, IdentityPointer>,
+
+    /// Registry record of the identity's name, claimed if version 1 lacked it
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = NameRecord::SIZE,
+        seeds = [
+            NAME_RECORD_SEED,
+            BusinessIdentity::stored_name_hash(&identity.try_borrow_data()?)?.as_ref()
+        ],
+        bump,
+        constraint = name_record.identity == Pubkey::default()
+            || name_record.identity == identity.key() @ IdentityError::NameTaken
+    )]
+    pub name_record: Account<'info, NameRecord>,
+
+    /// CHECK: Must match the authority stored in the identity
+    #[account(mut)]
+    pub authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(reference: Pubkey)]
+pub struct CreateInvoice<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired,
+        constraint = identity.kind()?.is_merchant() @ IdentityError::NotAMerchant
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the creator, when it is a staff member
+    pub staff: Option<Account<'info, StaffMember>>,
+
+    /// Terminal whose device is the creator, when created at a POS
+    #[account(mut)]
+    pub terminal: Option<Account<'info, Terminal>>,
+
+    /// Store the invoice is attributed to, if any
+    #[account(
+        has_one = identity,
+        constraint = store.active @ IdentityError::StoreInactive
+    )]
+    pub store: Option<Account<'info, Store>>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Invoice::SIZE,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), reference.as_ref()],
+        bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// The identity authority, a staff member or a terminal device
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelInvoice<'info> {
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = is_authorized(&identity, &authority.key(), staff.as_deref(), ROLE_CREATE_INVOICES)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub staff: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        constraint = invoice.status == InvoiceStatus::Open as u8 @ IdentityError::InvoiceNotOpen
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// The identity authority, or a staff member with `ROLE_CREATE_INVOICES`
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CloseExpiredInvoice<'info> {
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        close = creator,
+        seeds = [INVOICE_SEED, invoice.identity.as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = creator
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// CHECK: Created the invoice and receives the rest of its rent
+    #[account(mut)]
+    pub creator: UncheckedAccount<'info>,
+
+    /// Receives the bounty
+    #[account(mut)]
+    pub caller: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct PayInvoice<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = identity.version == IDENTITY_VERSION @ IdentityError::MigrationRequired
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Store the invoice is attributed to, required if it has one
+    #[account(constraint = store.active @ IdentityError::StoreInactive)]
+    pub store: Option<Account<'info, Store>>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        has_one = mint,
+        constraint = invoice.status == InvoiceStatus::Open as u8 @ IdentityError::InvoiceNotOpen,
+        constraint = invoice.store == store.as_ref().map(|store| store.key())
+            @ IdentityError::InvoiceStoreMismatch,
+        constraint = invoice.escrow_release_window.is_none() @ IdentityError::EscrowRequired
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = payer,
+        token::token_program = token_program
+    )]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Settlement destination of the store or identity for the mint
+    #[account(
+        mut,
+        constraint = identity.settlement_account(store.as_deref(), &mint.key())
+            == Some(settlement_token_account.key()) @ IdentityError::NoSettlementAccount
+    )]
+    pub settlement_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub payer: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RefundInvoice<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump,
+        constraint = is_authorized(&identity, &authority.key(), staff.as_deref(), ROLE_ISSUE_REFUNDS)?
+            @ IdentityError::Unauthorized
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Staff record of the signer, when it is not the identity authority
+    pub staff: Option<Account<'info, StaffMember>>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        has_one = mint,
+        constraint = invoice.status == InvoiceStatus::Paid as u8 @ IdentityError::InvoiceNotPaid
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Refund::SIZE,
+        seeds = [
+            REFUND_SEED,
+            invoice.key().as_ref(),
+            invoice.refund_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub refund: Account<'info, Refund>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// Merchant token account the refund is paid from
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program
+    )]
+    pub source_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Token account of the wallet that paid the invoice
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+        constraint = invoice.payer == Some(payer_token_account.owner) @ IdentityError::RefundPayerMismatch
+    )]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// The identity authority, or a staff member with `ROLE_ISSUE_REFUNDS`
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct PayInvoiceEscrow<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused,
+        constraint = config.arbiter != Pubkey::default() @ IdentityError::NoArbiter
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        has_one = mint,
+        constraint = invoice.status == InvoiceStatus::Open as u8 @ IdentityError::InvoiceNotOpen,
+        constraint = invoice.store == store.as_ref().map(|store| store.key())
+            @ IdentityError::InvoiceStoreMismatch
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// Store the invoice is attributed to, required if it has one
+    #[account(
+        mut,
+        constraint = store.active @ IdentityError::StoreInactive
+    )]
+    pub store: Option<Account<'info, Store>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [ESCROW_VAULT_SEED, invoice.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = invoice,
+        token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = payer,
+        token::token_program = token_program
+    )]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimEscrow<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Store the invoice is attributed to, required if it has one
+    #[account(mut)]
+    pub store: Option<Account<'info, Store>>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        has_one = mint,
+        constraint = invoice.status == InvoiceStatus::Escrowed as u8 @ IdentityError::InvoiceNotEscrowed,
+        constraint = invoice.store == store.as_ref().map(|store| store.key())
+            @ IdentityError::InvoiceStoreMismatch,
+        constraint = invoice.payer == Some(payer.key()) @ IdentityError::InvoicePayerMismatch
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    #[account(
+        mut,
+        seeds = [ESCROW_VAULT_SEED, invoice.key().as_ref()],
+        bump
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// Settlement destination of the store or identity for the mint
+    #[account(
+        mut,
+        constraint = identity.settlement_account(store.as_deref(), &mint.key())
+            == Some(settlement_token_account.key()) @ IdentityError::NoSettlementAccount
+    )]
+    pub settlement_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The invoice payer, who receives the vault's rent
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct OpenDispute<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.arbiter != Pubkey::default() @ IdentityError::NoArbiter
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, invoice.identity.as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        constraint = invoice.status == InvoiceStatus::Escrowed as u8 @ IdentityError::InvoiceNotEscrowed,
+        constraint = invoice.payer == Some(payer.key()) @ IdentityError::Unauthorized
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    pub payer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = arbiter @ IdentityError::Unauthorized,
+        constraint = !config.is_paused(PAUSE_PAYMENTS) @ IdentityError::ProgramPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [IDENTITY_SEED, identity.creator.as_ref()],
+        bump = identity.bump
+    )]
+    pub identity: Account<'info, BusinessIdentity>,
+
+    /// Store the invoice is attributed to, required if it has one
+    #[account(mut)]
+    pub store: Option<Account<'info, Store>>,
+
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, identity.key().as_ref(), invoice.reference.as_ref()],
+        bump = invoice.bump,
+        has_one = identity,
+        has_one = mint,
+        constraint = invoice.status == InvoiceStatus::Disputed as u8 @ IdentityError::InvoiceNotDisputed,
+        constraint = invoice.store == store.as_ref().map(|store| store.key())
+            @ IdentityError::InvoiceStoreMismatch,
+        constraint = invoice.payer == Some(payer.key()) @ IdentityError::InvoicePayerMismatch
+    )]
+    pub invoice: Account<'info, Invoice>,
+
+    /// Record of the payer's share, required unless it is zero
+    #[account(
+        init,
+        payer = arbiter,
+        space = Refund::SIZE,
+        seeds = [
+            REFUND_SEED,
+            invoice.key().as_ref(),
+            invoice.refund_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub refund: Option<Account<'info, Refund>>,
+
+    #[account(
+        mut,
+        seeds = [ESCROW_VAULT_SEED, invoice.key().as_ref()],
+        bump
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// Token account of the payer for their share
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = payer,
+        token::token_program = token_program
+    )]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Settlement destination of the store or identity for the mint
+    #[account(
+        mut,
+        constraint = identity.settlement_account(store.as_deref(), &mint.key())
+            == Some(settlement_token_account.key()) @ IdentityError::NoSettlementAccount
+    )]
+    pub settlement_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The invoice payer, who receives the vault's rent
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub arbiter: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts needed to move funds out of an invoice's escrow vault
+struct EscrowAccounts<'a, 'info> {
+    invoice: &'a Account<'info, Invoice>,
+    vault: &'a InterfaceAccount<'info, TokenAccount>,
+    mint: &'a InterfaceAccount<'info, Mint>,
+    token_program: &'a Interface<'info, TokenInterface>,
+}
+
+impl<'info> EscrowAccounts<'_, 'info> {
+    /// Transfer `amount` from the vault to `to`, signed by the invoice PDA
+    fn release(&self, to: &InterfaceAccount<'info, TokenAccount>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Ok(());
+        }
+        transfer_checked(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                TransferChecked {
+                    from: self.vault.to_account_info(),
+                    mint: self.mint.to_account_info(),
+                    to: to.to_account_info(),
+                    authority: self.invoice.to_account_info(),
+                },
+                &[&self.invoice.signer_seeds()],
+            ),
+            amount,
+            self.mint.decimals,
+        )
+    }
+
+    /// Close the emptied vault, returning its rent to `destination`
+    fn close_vault(&self, destination: &AccountInfo<'info>) -> Result<()> {
+        token_interface::close_account(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.vault.to_account_info(),
+                destination: destination.clone(),
+                authority: self.invoice.to_account_info(),
+            },
+            &[&self.invoice.signer_seeds()],
+        ))
+    }
+}
+
+#[account]
+pub struct BusinessIdentity {
+    /// The wallet that owns this identity
+    pub authority: Pubkey,
+    /// Layout version, see [`IDENTITY_VERSION`]
+    pub version: u8,
+    /// Type of identity, see [`IdentityType`]
+    pub identity_type: u8,
+    /// Business name, trimmed (max 64 characters and 128 bytes)
+    pub name: String,
+    /// Logo URI on Arweave/Irys (max 200 bytes)
+    pub logo_uri: String,
+    /// Unix timestamp when created
+    pub created_at: i64,
+    /// Unix timestamp when last updated
+    pub updated_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+    /// Key the identity PDA is derived from (never changes)
+    pub creator: Pubkey,
+    /// Wallet proposed as the next authority, if a transfer is pending
+    pub pending_authority: Option<Pubkey>,
+    /// Short description of the business (max 280 bytes)
+    pub description: Option<String>,
+    /// Website URL, `https://` only (max 200 bytes)
+    pub website: Option<String>,
+    /// Support email, phone or handle (max 100 bytes)
+    pub support_contact: Option<String>,
+    /// ISO 3166-1 alpha-2 country code
+    pub country_code: Option<String>,
+    /// Business category, e.g. "cafe" (max 32 bytes)
+    pub business_category: Option<String>,
+    /// Free-form opening hours (max 128 bytes)
+    pub opening_hours: Option<String>,
+    /// Registered @handle, without the leading `@`
+    pub handle: Option<String>,
+    /// SHA-256 of the image served at `logo_uri`
+    pub logo_sha256: Option<[u8; 32]>,
+    /// MIME type of the image served at `logo_uri`, e.g. "image/png"
+    pub logo_mime: Option<String>,
+    /// Wallet that receives native SOL payments
+    pub settlement_sol: Option<Pubkey>,
+    /// Token accounts that receive payments, one per mint
+    pub settlement_accounts: Vec<SettlementDestination>,
+    /// Number of stores created, used as the next store index
+    pub store_count: u32,
+    /// Number of store accounts not yet closed
+    pub open_stores: u32,
+    /// Number of escrowed or disputed invoices awaiting settlement
+    pub open_escrows: u32,
+}
+
+impl BusinessIdentity {
+    /// Account size with empty strings and no profile fields
+    /// 8 (discriminator) + 32 (authority) + 1 (version) + 1 (identity_type) +
+    /// 4 (name string) + 4 (logo_uri string) + 8 (created_at) + 8 (updated_at) + 1 (bump) +
+    /// 32 (creator) + 1+32 (pending_authority) + 6*1 (profile options) + 1 (handle option) +
+    /// 1+32 (logo_sha256) + 1 (logo_mime option) + 1+32 (settlement_sol) +
+    /// 4 (settlement_accounts vec) + 4 (store_count) + 4 (open_stores) + 4 (open_escrows)
+    pub const BASE_SIZE: usize = 8
+        + 32
+        + 1
+        + 1
+        + 4
+        + 4
+        + 8
+        + 8
+        + 1
+        + 32
+        + (1 + 32)
+        + 6
+        + 1
+        + (1 + 32)
+        + 1
+        + (1 + 32)
+        + 4
+        + 4
+        + 4
+        + 4;
+
+    /// Maximum account size, with every string at its maximum length
+    pub const SIZE: usize = Self::BASE_SIZE
+        + MAX_NAME_LENGTH
+        + MAX_LOGO_URI_LENGTH
+        + (4 + MAX_DESCRIPTION_LENGTH)
+        + (4 + MAX_WEBSITE_LENGTH)
+        + (4 + MAX_SUPPORT_CONTACT_LENGTH)
+        + (4 + COUNTRY_CODE_LENGTH)
+        + (4 + MAX_BUSINESS_CATEGORY_LENGTH)
+        + (4 + MAX_OPENING_HOURS_LENGTH)
+        + (4 + MAX_HANDLE_LENGTH)
+        + (4 + MAX_LOGO_MIME_LENGTH)
+        + SettlementDestination::SIZE * MAX_SETTLEMENT_ACCOUNTS;
+
+    /// Account size needed for the current contents
+    pub fn space(&self) -> usize {
+        let optional: usize = [
+            &self.description,
+            &self.website,
+            &self.support_contact,
+            &self.country_code,
+            &self.business_category,
+            &self.opening_hours,
+            &self.handle,
+            &self.logo_mime,
+        ]
+        .iter()
+        .map(|field| field.as_ref().map_or(0, |value| 4 + value.len()))
+        .sum();
+        Self::BASE_SIZE
+            + self.name.len()
+            + self.logo_uri.len()
+            + optional
+            + SettlementDestination::SIZE * self.settlement_accounts.len()
+    }
+
+    /// Token account that receives payments in `mint`, preferring the
+    /// destination of `store` over the identity's
+    pub fn settlement_account(&self, store: Option<&Store>, mint: &Pubkey) -> Option<Pubkey> {
+        store
+            .and_then(|store| SettlementDestination::find(&store.settlement_accounts, mint))
+            .or_else(|| SettlementDestination::find(&self.settlement_accounts, mint))
+    }
+
+    /// The stored profile fields, as a patch that would set them
+    pub fn profile(&self) -> ProfileUpdate {
+        ProfileUpdate {
+            description: self.description.clone(),
+            website: self.website.clone(),
+            support_contact: self.support_contact.clone(),
+            country_code: self.country_code.clone(),
+            business_category: self.business_category.clone(),
+            opening_hours: self.opening_hours.clone(),
+        }
+    }
+
+    /// Apply a profile patch, validating each field that is set
+    pub fn apply_profile(&mut self, profile: ProfileUpdate) -> Result<()> {
+        patch_field(
+            &mut self.description,
+            profile.description,
+            MAX_DESCRIPTION_LENGTH,
+        )?;
+        patch_field(&mut self.website, profile.website, MAX_WEBSITE_LENGTH)?;
+        patch_field(
+            &mut self.support_contact,
+            profile.support_contact,
+            MAX_SUPPORT_CONTACT_LENGTH,
+        )?;
+        patch_field(
+            &mut self.country_code,
+            profile.country_code,
+            COUNTRY_CODE_LENGTH,
+        )?;
+        patch_field(
+            &mut self.business_category,
+            profile.business_category,
+            MAX_BUSINESS_CATEGORY_LENGTH,
+        )?;
+        patch_field(
+            &mut self.opening_hours,
+            profile.opening_hours,
+            MAX_OPENING_HOURS_LENGTH,
+        )?;
+
+        if let Some(country_code) = &self.country_code {
+            require!(
+                country_code.len() == COUNTRY_CODE_LENGTH
+                    && country_code.bytes().all(|b| b.is_ascii_uppercase()),
+                IdentityError::InvalidCountryCode
+            );
+        }
+        if let Some(website) = &self.website {
+            require!(
+                website.len() > WEBSITE_SCHEME.len()
+                    && website.starts_with(WEBSITE_SCHEME)
+                    && website.bytes().all(|b| b.is_ascii_graphic()),
+                IdentityError::InvalidWebsite
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Offset of the version byte in the account data
+    pub const VERSION_OFFSET: usize = 8 + 32;
+
+    /// Check fetched logo bytes against the on-chain SHA-256 commitment
+    ///
+    /// Clients should also compare the served content type with
+    /// `logo_mime` before rendering the image.
+    pub fn verify_logo(&self, logo: &[u8]) -> Result<()> {
+        let expected = self.logo_sha256.ok_or(IdentityError::NoLogoCommitment)?;
+        require!(
+            hash(logo).to_bytes() == expected,
+            IdentityError::LogoHashMismatch
+        );
+        Ok(())
+    }
+
+    /// Decode the stored identity type
+    pub fn kind(&self) -> Result<IdentityType> {
+        IdentityType::try_from(self.identity_type)
+    }
+
+    /// Name registry hash of raw identity account data in any layout
+    pub fn stored_name_hash(data: &[u8]) -> Result<[u8; 32]> {
+        Ok(normalized_name_hash(
+            &Self::try_deserialize_any_version(data)?.name,
+        ))
+    }
+
+    /// Read the layout version of raw identity account data
+    pub fn stored_version(data: &[u8]) -> Result<u8> {
+        require!(
+            data.len() > Self::VERSION_OFFSET,
+            ErrorCode::AccountDidNotDeserialize
+        );
+        require!(
+            data[..8] == BusinessIdentity::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+        Ok(data[Self::VERSION_OFFSET])
+    }
+
+    /// Decode raw identity account data in any historical layout
+    ///
+    /// Fields missing from older layouts are filled with their defaults.
+    pub fn try_deserialize_any_version(data: &[u8]) -> Result<Self> {
+        match Self::stored_version(data)? {
+            LEGACY_IDENTITY_VERSION => decode_layout::<BusinessIdentityV1>(data).map(Into::into),
+            IDENTITY_VERSION => Self::try_deserialize(&mut &data[..]),
+            _ => err!(IdentityError::UnsupportedVersion),
+        }
+    }
+}
+
+/// Decode account data (discriminator included) in a legacy layout
+fn decode_layout<T: AnchorDeserialize>(data: &[u8]) -> Result<T> {
+    T::deserialize(&mut &data[8..]).map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+}
+
+/// Original `BusinessIdentity` layout (version 1)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct BusinessIdentityV1 {
+    pub authority: Pubkey,
+    pub identity_type: u8,
+    pub name: String,
+    pub logo_uri: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl From<BusinessIdentityV1> for BusinessIdentity {
+    fn from(legacy: BusinessIdentityV1) -> Self {
+        BusinessIdentity {
+            authority: legacy.authority,
+            version: IDENTITY_VERSION,
+            identity_type: legacy.identity_type,
+            name: legacy.name,
+            logo_uri: legacy.logo_uri,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            bump: legacy.bump,
+            creator: legacy.authority,
+            pending_authority: None,
+            description: None,
+            website: None,
+            support_contact: None,
+            country_code: None,
+            business_category: None,
+            opening_hours: None,
+            handle: None,
+            logo_sha256: None,
+            logo_mime: None,
+            settlement_sol: None,
+            settlement_accounts: Vec::new(),
+            store_count: 0,
+            open_stores: 0,
+            open_escrows: 0,
+        }
+    }
+}
+
+/// Registry entry reserving a normalized name for one identity
+#[account]
+pub struct NameRecord {
+    /// The identity holding the name
+    pub identity: Pubkey,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl NameRecord {
+    /// 8 (discriminator) + 32 (identity) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 1;
+}
+
+/// Hash of a name as used in the name registry seeds
+///
+/// Names are trimmed, NFKC-normalized and lowercased first, so that
+/// visually equal names map to the same record.
+pub fn normalized_name_hash(name: &str) -> [u8; 32] {
+    let normalized: String = name.trim().nfkc().flat_map(char::to_lowercase).collect();
+    hash(normalized.as_bytes()).to_bytes()
+}
+
+/// Global program settings (singleton)
+#[account]
+pub struct ProgramConfig {
+    /// Key allowed to manage verifiers, reserved handles and settings
+    pub admin: Pubkey,
+    /// Key proposed as the next admin, if a rotation is pending
+    pub pending_admin: Option<Pubkey>,
+    /// PDA bump seed
+    pub bump: u8,
+    /// Paused instruction groups, see the `PAUSE_*` flags
+    pub paused: u8,
+    /// Lamports paid to whoever closes an expired invoice, out of its rent
+    pub crank_bounty: u64,
+    /// Key that resolves disputes over escrowed payments
+    pub arbiter: Pubkey,
+    /// Reserved for future settings
+    pub reserved: [u8; 23],
+}
+
+impl ProgramConfig {
+    /// 8 (discriminator) + 32 (admin) + 1+32 (pending_admin) + 1 (bump) + 1 (paused) +
+    /// 8 (crank_bounty) + 32 (arbiter) + 23 (reserved)
+    pub const SIZE: usize = 8 + 32 + (1 + 32) + 1 + 1 + 8 + 32 + 23;
+
+    /// Whether any of the given `PAUSE_*` flags is set
+    pub fn is_paused(&self, flags: u8) -> bool {
+        self.paused & flags != 0
+    }
+}
+
+/// Record mapping an @handle to the identity that owns it
+#[account]
+pub struct HandleRecord {
+    /// The identity owning the handle
+    pub identity: Pubkey,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl HandleRecord {
+    /// 8 (discriminator) + 32 (identity) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 1;
+}
+
+/// Marker that a handle is reserved by the program admin
+#[account]
+pub struct ReservedHandle {
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl ReservedHandle {
+    /// 8 (discriminator) + 1 (bump)
+    pub const SIZE: usize = 8 + 1;
+}
+
+/// Whether `handle` is a well-formed, URL-safe handle
+pub fn is_valid_handle(handle: &str) -> bool {
+    (MIN_HANDLE_LENGTH..=MAX_HANDLE_LENGTH).contains(&handle.len())
+        && handle
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// Keys that can together recover an identity to a new wallet
+#[account]
+pub struct GuardianSet {
+    /// The identity the guardians protect
+    pub identity: Pubkey,
+    /// Identity authority the guardians were set for
+    pub authority: Pubkey,
+    /// Guardian keys
+    pub guardians: Vec<Pubkey>,
+    /// Approvals needed to start the recovery timelock
+    pub threshold: u8,
+    /// Seconds between reaching the threshold and completing a recovery
+    pub timelock: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl GuardianSet {
+    /// 8 (discriminator) + 32 (identity) + 32 (authority) +
+    /// 4+32*MAX_GUARDIANS (guardians) + 1 (threshold) + 8 (timelock) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 32 + (4 + 32 * MAX_GUARDIANS) + 1 + 8 + 1;
+}
+
+/// A recovery in progress; at most one per identity
+#[account]
+pub struct Recovery {
+    /// The identity being recovered
+    pub identity: Pubkey,
+    /// Identity authority being recovered from
+    pub authority: Pubkey,
+    /// Wallet that becomes the authority
+    pub new_authority: Pubkey,
+    /// Guardian who started the recovery and receives its rent
+    pub initiator: Pubkey,
+    /// Guardians who approved
+    pub approvals: Vec<Pubkey>,
+    /// Unix timestamp from which the recovery can be completed, once
+    /// enough guardians have approved
+    pub unlocks_at: Option<i64>,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Recovery {
+    /// 8 (discriminator) + 32 (identity) + 32 (authority) + 32 (new_authority) +
+    /// 32 (initiator) + 4+32*MAX_GUARDIANS (approvals) + 1+8 (unlocks_at) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 32 + 32 + 32 + (4 + 32 * MAX_GUARDIANS) + (1 + 8) + 1;
+
+    fn start_timelock_if_approved(&mut self, guardian_set: &GuardianSet, now: i64) {
+        if self.unlocks_at.is_none() && self.approvals.len() >= guardian_set.threshold as usize {
+            self.unlocks_at = Some(now.saturating_add(guardian_set.timelock));
+        }
+    }
+}
+
+/// A key allowed to act on an identity on behalf of its authority
+#[account]
+pub struct StaffMember {
+    /// The identity the staff member works for
+    pub identity: Pubkey,
+    /// Identity authority that granted the permissions
+    pub authority: Pubkey,
+    /// The staff member's signing key
+    pub staff: Pubkey,
+    /// What the staff member may do
+    pub permissions: StaffPermissions,
+    /// Unix timestamp when added
+    pub created_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl StaffMember {
+    /// 8 (discriminator) + 32 (identity) + 32 (authority) + 32 (staff) + 1 (roles) +
+    /// 1+8 (expires_at) + 1+8 (max_invoice_amount) + 1+8 (max_refund_amount) +
+    /// 8 (created_at) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 32 + 32 + 1 + (1 + 8) + (1 + 8) + (1 + 8) + 8 + 1;
+
+    /// Whether the staff member holds all of `roles` at `now`
+    pub fn has_roles(&self, roles: u8, now: i64) -> bool {
+        self.permissions.roles & roles == roles
+            && self
+                .permissions
+                .expires_at
+                .map_or(true, |expires_at| now < expires_at)
+    }
+
+    /// Check an invoice amount against the staff member's limit
+    pub fn check_invoice_amount(&self, amount: u64) -> Result<()> {
+        require!(
+            self.permissions
+                .max_invoice_amount
+                .map_or(true, |max| amount <= max),
+            IdentityError::StaffLimitExceeded
+        );
+        Ok(())
+    }
+
+    /// Check the total refunded on an invoice, after a refund by the staff
+    /// member, against their limit
+    pub fn check_refund_amount(&self, refunded_amount: u64) -> Result<()> {
+        require!(
+            self.permissions
+                .max_refund_amount
+                .map_or(true, |max| refunded_amount <= max),
+            IdentityError::StaffLimitExceeded
+        );
+        Ok(())
+    }
+}
+
+/// Roles and limits granted to a [`StaffMember`]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct StaffPermissions {
+    /// Combination of the `ROLE_*` flags
+    pub roles: u8,
+    /// Unix timestamp after which the staff member can no longer act
+    pub expires_at: Option<i64>,
+    /// Largest invoice the staff member may create, in base units
+    pub max_invoice_amount: Option<u64>,
+    /// Largest total the staff member may refund on one invoice, in base units
+    pub max_refund_amount: Option<u64>,
+}
+
+impl StaffPermissions {
+    /// Whether these permissions grant nothing beyond `limit`
+    fn is_within(&self, limit: &StaffPermissions) -> bool {
+        fn within<T: PartialOrd>(value: Option<T>, limit: Option<T>) -> bool {
+            limit.map_or(true, |limit| value.is_some_and(|value| value <= limit))
+        }
+        self.roles & !limit.roles == 0
+            && within(self.expires_at, limit.expires_at)
+            && within(self.max_invoice_amount, limit.max_invoice_amount)
+            && within(self.max_refund_amount, limit.max_refund_amount)
+    }
+
+    fn validate(&self) -> Result<()> {
+        require!(
+            self.roles != 0 && self.roles & !ALL_ROLES == 0,
+            IdentityError::InvalidStaffRoles
+        );
+        if let Some(expires_at) = self.expires_at {
+            require!(
+                expires_at > Clock::get()?.unix_timestamp,
+                IdentityError::InvalidStaffExpiry
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Check that a staff manager signing for `identity` holds everything in
+/// `permissions`
+///
+/// Passes when `signer` is the identity authority. Meant to follow an
+/// [`is_authorized`] check with `ROLE_MANAGE_STAFF`.
+fn check_staff_grant(
+    identity: &BusinessIdentity,
+    signer: &Signer,
+    manager: Option<&StaffMember>,
+    permissions: &[&StaffPermissions],
+) -> Result<()> {
+    if identity.authority == signer.key() {
+        return Ok(());
+    }
+    let manager = manager.ok_or(IdentityError::Unauthorized)?;
+    require!(
+        permissions
+            .iter()
+            .all(|permissions| permissions.is_within(&manager.permissions)),
+        IdentityError::StaffGrantExceeded
+    );
+    Ok(())
+}
+
+/// Whether `signer` may act on `identity` with `roles`
+///
+/// The authority may always act. Anyone else must provide their
+/// [`StaffMember`] record for this identity, granted by its current
+/// authority, holding the roles and not expired. Meant for
+/// `constraint = ...` checks in account structs.
+pub fn is_authorized(
+    identity: &Account<BusinessIdentity>,
+    signer: &Pubkey,
+    staff: Option<&StaffMember>,
+    roles: u8,
+) -> Result<bool> {
+    if identity.authority == *signer {
+        return Ok(true);
+    }
+    let Some(staff) = staff else {
+        return Ok(false);
+    };
+    Ok(staff.identity == identity.key()
+        && staff.authority == identity.authority
+        && staff.staff == *signer
+        && staff.has_roles(roles, Clock::get()?.unix_timestamp))
+}
+
+/// An m-of-n set of keys acting through its signer PDA
+#[account]
+pub struct Multisig {
+    /// Key the multisig address is derived from
+    pub create_key: Pubkey,
+    /// Keys that may propose, approve and execute
+    pub members: Vec<Pubkey>,
+    /// Approvals needed to execute a proposal
+    pub threshold: u8,
+    /// Bumped on member changes to invalidate pending proposals
+    pub generation: u32,
+    /// Number of proposals created, used as the next proposal index
+    pub proposal_count: u64,
+    /// PDA bump seed
+    pub bump: u8,
+    /// Bump seed of the signer PDA
+    pub signer_bump: u8,
+}
+
+impl Multisig {
+    /// 8 (discriminator) + 32 (create_key) + 4+32*MAX_MULTISIG_MEMBERS (members) +
+    /// 1 (threshold) + 4 (generation) + 8 (proposal_count) + 1 (bump) + 1 (signer_bump)
+    pub const SIZE: usize = 8 + 32 + (4 + 32 * MAX_MULTISIG_MEMBERS) + 1 + 4 + 8 + 1 + 1;
+
+    /// Position of `key` among the members
+    pub fn member_index(&self, key: &Pubkey) -> Result<usize> {
+        self.members
+            .iter()
+            .position(|member| member == key)
+            .ok_or_else(|| error!(IdentityError::NotMultisigMember))
+    }
+
+    fn set_members(&mut self, members: Vec<Pubkey>, threshold: u8, signer: Pubkey) -> Result<()> {
+        require!(
+            !members.is_empty()
+                && members.len() <= MAX_MULTISIG_MEMBERS
+                && !members.contains(&signer)
+                && members
+                    .iter()
+                    .enumerate()
+                    .all(|(i, member)| !members[..i].contains(member)),
+            IdentityError::InvalidMultisigMembers
+        );
+        require!(
+            threshold > 0 && threshold as usize <= members.len(),
+            IdentityError::InvalidMultisigThreshold
+        );
+        self.members = members;
+        self.threshold = threshold;
+        Ok(())
+    }
+}
+
+/// An instruction waiting for multisig approval
+#[account]
+pub struct Proposal {
+    /// The multisig that has to approve
+    pub multisig: Pubkey,
+    /// Index of the proposal within its multisig
+    pub index: u64,
+    /// Member who created the proposal and receives its rent
+    pub proposer: Pubkey,
+    /// Multisig generation the proposal was created under
+    pub generation: u32,
+    /// Program the instruction is sent to
+    pub program_id: Pubkey,
+    /// Accounts of the instruction
+    pub accounts: Vec<ProposalAccount>,
+    /// Data of the instruction
+    pub data: Vec<u8>,
+    /// Bitmask of approving members, by member index
+    pub approvals: u16,
+    /// Set once the instruction has been invoked
+    pub executed: bool,
+    /// Unix timestamp when proposed
+    pub created_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Proposal {
+    /// 8 (discriminator) + 32 (multisig) + 8 (index) + 32 (proposer) + 4 (generation) +
+    /// 32 (program_id) + 4+34*MAX_PROPOSAL_ACCOUNTS (accounts) +
+    /// 4+MAX_PROPOSAL_DATA_LENGTH (data) + 2 (approvals) + 1 (executed) +
+    /// 8 (created_at) + 1 (bump)
+    pub const SIZE: usize = 8
+        + 32
+        + 8
+        + 32
+        + 4
+        + 32
+        + (4 + 34 * MAX_PROPOSAL_ACCOUNTS)
+        + (4 + MAX_PROPOSAL_DATA_LENGTH)
+        + 2
+        + 1
+        + 8
+        + 1;
+}
+
+/// Account of a proposed instruction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ProposalAccount {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+/// Allowlist entry for a key that may issue attestations
+#[account]
+pub struct VerifierRecord {
+    /// The verifier's signing key
+    pub verifier: Pubkey,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl VerifierRecord {
+    /// 8 (discriminator) + 32 (verifier) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 1;
+}
+
+/// A verifier's claim about an identity, such as a passed KYB check
+#[account]
+pub struct Attestation {
+    /// The identity the claim is about
+    pub identity: Pubkey,
+    /// Identity authority when the claim was issued
+    pub authority: Pubkey,
+    /// Normalized hash of the identity name when the claim was issued
+    pub name_hash: [u8; 32],
+    /// Creation time of the identity the claim was issued to
+    pub identity_created_at: i64,
+    /// The verifier that issued the claim
+    pub verifier: Pubkey,
+    /// Kind of claim, see [`ClaimType`]
+    pub claim_type: u8,
+    /// Hash of the off-chain evidence backing the claim
+    pub evidence_hash: [u8; 32],
+    /// Unix timestamp when issued
+    pub issued_at: i64,
+    /// Unix timestamp after which the claim no longer holds
+    pub expires_at: i64,
+    /// Set by the verifier to withdraw the claim
+    pub revoked: bool,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Attestation {
+    /// 8 (discriminator) + 32 (identity) + 32 (authority) + 32 (name_hash) +
+    /// 8 (identity_created_at) + 32 (verifier) + 1 (claim_type) +
+    /// 32 (evidence_hash) + 8 (issued_at) + 8 (expires_at) + 1 (revoked) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 32 + 32 + 8 + 32 + 1 + 32 + 8 + 8 + 1 + 1;
+
+    /// Whether the claim holds for `identity` at `now`
+    ///
+    /// A claim lapses when the identity changes hands or names, or is
+    /// closed and created again at the same address, which gives it a new
+    /// creation time. Clients
+    /// showing a badge should also check that the verifier's
+    /// [`VerifierRecord`] still exists.
+    pub fn is_valid(&self, identity: &BusinessIdentity, now: i64) -> bool {
+        !self.revoked
+            && now < self.expires_at
+            && self.authority == identity.authority
+            && self.name_hash == normalized_name_hash(&identity.name)
+            && self.identity_created_at == identity.created_at
+    }
+}
+
+/// Kind of claim an [`Attestation`] makes
+///
+/// The discriminants are stored on-chain and used in PDA seeds, so they
+/// must never be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClaimType {
+    /// Business registration verified (KYB)
+    BusinessVerified = 1,
+    /// Physical address verified
+    AddressVerified = 2,
+    /// Control of the website domain verified
+    DomainVerified = 3,
+    /// Registered charity or nonprofit status verified
+    NonprofitVerified = 4,
+}
+
+impl TryFrom<u8> for ClaimType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(ClaimType::BusinessVerified),
+            2 => Ok(ClaimType::AddressVerified),
+            3 => Ok(ClaimType::DomainVerified),
+            4 => Ok(ClaimType::NonprofitVerified),
+            _ => err!(IdentityError::InvalidClaimType),
+        }
+    }
+}
+
+/// Close a name record if it is held by `identity`
+///
+/// Records that do not exist or belong to another identity are left
+/// alone.
+fn release_name<'info>(
+    record: &AccountInfo<'info>,
+    identity: Pubkey,
+    refund_to: &AccountInfo<'info>,
+) -> Result<()> {
+    if record.owner != &crate::ID {
+        return Ok(());
+    }
+    let held = NameRecord::try_deserialize(&mut &record.try_borrow_data()?[..])?;
+    if held.identity == identity {
+        close_account(record, refund_to)?;
+    }
+    Ok(())
+}
+
+/// Close a program-owned account, sending its lamports to `destination`
+fn close_account<'info>(
+    account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    let lamports = account.lamports();
+    **account.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? += lamports;
+    account.assign(&System::id());
+    account.realloc(0, false)?;
+    Ok(())
+}
+
+/// Check a business name and return it without surrounding whitespace
+///
+/// Control, zero-width and bidirectional formatting characters are
+/// rejected, since they can make a name render like a different one.
+pub fn validate_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    for c in name.chars() {
+        require!(!c.is_control(), IdentityError::NameHasControlCharacter);
+        require!(!is_zero_width(c), IdentityError::NameHasZeroWidthCharacter);
+        require!(!is_bidi_control(c), IdentityError::NameHasBidiCharacter);
+    }
+    let chars = name.chars().count();
+    require!(
+        chars > 0 && chars <= MAX_NAME_CHARS,
+        IdentityError::InvalidNameLength
+    );
+    require!(name.len() <= MAX_NAME_LENGTH, IdentityError::NameTooLong);
+    Ok(name)
+}
+
+/// Check a logo URI; an empty URI means no logo
+pub fn validate_logo_uri(logo_uri: &str) -> Result<()> {
+    require!(
+        logo_uri.len() <= MAX_LOGO_URI_LENGTH,
+        IdentityError::InvalidLogoUriLength
+    );
+    if logo_uri.is_empty() {
+        return Ok(());
+    }
+    require!(
+        logo_uri.bytes().all(|b| b.is_ascii_graphic()),
+        IdentityError::LogoUriHasInvalidCharacter
+    );
+    require!(
+        ALLOWED_LOGO_URI_SCHEMES
+            .iter()
+            .any(|scheme| logo_uri.len() > scheme.len() && logo_uri.starts_with(scheme)),
+        IdentityError::UnsupportedLogoUriScheme
+    );
+    Ok(())
+}
+
+/// Check the logo commitment given alongside `logo_uri`
+fn validate_logo_commitment(
+    logo_uri: &str,
+    logo_sha256: &Option<[u8; 32]>,
+    logo_mime: &Option<String>,
+) -> Result<()> {
+    require!(
+        !logo_uri.is_empty() || (logo_sha256.is_none() && logo_mime.is_none()),
+        IdentityError::LogoCommitmentWithoutUri
+    );
+    if let Some(mime) = logo_mime {
+        require!(
+            mime.len() <= MAX_LOGO_MIME_LENGTH
+                && mime.len() > "image/".len()
+                && mime.starts_with("image/")
+                && mime.bytes().all(|b| {
+                    b.is_ascii_lowercase() || b.is_ascii_digit() || b"/.+-".contains(&b)
+                }),
+            IdentityError::InvalidLogoMime
+        );
+    }
+    Ok(())
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{180E}' | '\u{200B}'..='\u{200D}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}'
+    )
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// A POS device allowed to act for an identity
+#[account]
+pub struct Terminal {
+    /// The identity the terminal belongs to
+    pub identity: Pubkey,
+    /// Identity authority that registered the terminal
+    pub authority: Pubkey,
+    /// Store the terminal is used at, if any
+    pub store: Option<Pubkey>,
+    /// The device's signing key
+    pub device: Pubkey,
+    /// Human-readable label, e.g. "Front counter" (max 32 bytes)
+    pub label: String,
+    /// Slot of the terminal's latest activity
+    pub last_seen_slot: u64,
+    /// Whether the terminal has been revoked
+    pub revoked: bool,
+    /// Unix timestamp when registered
+    pub created_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Terminal {
+    /// 8 (discriminator) + 32 (identity) + 32 (authority) + 1+32 (store) + 32 (device) +
+    /// 4+MAX_TERMINAL_LABEL_LENGTH (label) + 8 (last_seen_slot) + 1 (revoked) +
+    /// 8 (created_at) + 1 (bump)
+    pub const SIZE: usize =
+        8 + 32 + 32 + (1 + 32) + 32 + (4 + MAX_TERMINAL_LABEL_LENGTH) + 8 + 1 + 8 + 1;
+
+    /// Whether the terminal may act for `identity`: not revoked and
+    /// registered by its current authority
+    pub fn is_live(&self, identity: &BusinessIdentity) -> bool {
+        !self.revoked && self.authority == identity.authority
+    }
+}
+
+/// Check that `terminal` is a non-revoked terminal of `identity` whose
+/// device is `signer`, and record its activity
+///
+/// Terminals registered under a previous authority no longer count until
+/// they are registered again.
+/// Meant for instructions that take an optional terminal and require
+/// its device to sign when one is passed.
+pub fn use_terminal(
+    terminal: &mut Terminal,
+    identity: &Account<BusinessIdentity>,
+    signer: &Signer,
+) -> Result<()> {
+    require_keys_eq!(
+        terminal.identity,
+        identity.key(),
+        IdentityError::Unauthorized
+    );
+    require_keys_eq!(
+        terminal.authority,
+        identity.authority,
+        IdentityError::Unauthorized
+    );
+    require_keys_eq!(terminal.device, signer.key(), IdentityError::Unauthorized);
+    require!(!terminal.revoked, IdentityError::TerminalRevoked);
+    terminal.last_seen_slot = Clock::get()?.slot;
+    Ok(())
+}
+
+/// A request for payment to an identity
+#[account]
+pub struct Invoice {
+    /// The identity being paid
+    pub identity: Pubkey,
+    /// Solana Pay reference key
+    pub reference: Pubkey,
+    /// Store the invoice is attributed to, if any
+    pub store: Option<Pubkey>,
+    /// Terminal the invoice was created at, if any
+    pub terminal: Option<Pubkey>,
+    /// Signer who created the invoice and paid its rent
+    pub creator: Pubkey,
+    /// Mint of the token to pay in
+    pub mint: Pubkey,
+    /// Amount due, in base units of `mint`
+    pub amount: u64,
+    /// Status, see [`InvoiceStatus`]
+    pub status: u8,
+    /// Memo or order id (max 64 bytes)
+    pub memo: String,
+    /// Unix timestamp when created
+    pub created_at: i64,
+    /// Unix timestamp after which the invoice can no longer be paid
+    pub expires_at: Option<i64>,
+    /// PDA bump seed
+    pub bump: u8,
+    /// Wallet that paid the invoice
+    pub payer: Option<Pubkey>,
+    /// Slot in which the invoice was paid
+    pub paid_slot: Option<u64>,
+    /// Total refunded so far, in base units of `mint`
+    pub refunded_amount: u64,
+    /// Number of refunds issued, used as the next refund index
+    pub refund_count: u16,
+    /// Seconds a payment stays in escrow, for escrowed invoices
+    pub escrow_release_window: Option<i64>,
+    /// Unix timestamp from which the merchant can claim the escrow
+    pub escrow_release_at: Option<i64>,
+}
+
+impl Invoice {
+    /// 8 (discriminator) + 32 (identity) + 32 (reference) + 1+32 (store) +
+    /// 1+32 (terminal) + 32 (creator) + 32 (mint) + 8 (amount) + 1 (status) +
+    /// 4+MAX_INVOICE_MEMO_LENGTH (memo) + 8 (created_at) + 1+8 (expires_at) + 1 (bump) +
+    /// 1+32 (payer) + 1+8 (paid_slot) + 8 (refunded_amount) + 2 (refund_count) +
+    /// 1+8 (escrow_release_window) + 1+8 (escrow_release_at)
+    pub const SIZE: usize = 8
+        + 32
+        + 32
+        + (1 + 32)
+        + (1 + 32)
+        + 32
+        + 32
+        + 8
+        + 1
+        + (4 + MAX_INVOICE_MEMO_LENGTH)
+        + 8
+        + (1 + 8)
+        + 1
+        + (1 + 32)
+        + (1 + 8)
+        + 8
+        + 2
+        + (1 + 8)
+        + (1 + 8);
+
+    /// Signer seeds of the invoice PDA, which owns its escrow vault
+    fn signer_seeds(&self) -> [&[u8]; 4] {
+        [
+            INVOICE_SEED,
+            self.identity.as_ref(),
+            self.reference.as_ref(),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+}
+
+/// Lifecycle of an [`Invoice`]
+///
+/// The discriminants are stored on-chain, so they must never be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InvoiceStatus {
+    Open = 1,
+    Paid = 2,
+    Cancelled = 3,
+    /// Paid and refunded in full
+    Refunded = 4,
+    /// Paid into escrow, not yet claimed by the merchant
+    Escrowed = 5,
+    /// Paid into escrow and disputed by the payer
+    Disputed = 6,
+}
+
+impl TryFrom<u8> for InvoiceStatus {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(InvoiceStatus::Open),
+            2 => Ok(InvoiceStatus::Paid),
+            3 => Ok(InvoiceStatus::Cancelled),
+            4 => Ok(InvoiceStatus::Refunded),
+            5 => Ok(InvoiceStatus::Escrowed),
+            6 => Ok(InvoiceStatus::Disputed),
+            _ => err!(IdentityError::InvalidInvoiceStatus),
+        }
+    }
+}
+
+/// Record of a refund of a paid invoice
+#[account]
+pub struct Refund {
+    /// The refunded invoice
+    pub invoice: Pubkey,
+    /// Index of the refund within its invoice
+    pub index: u16,
+    /// Amount refunded, in base units of the invoice mint
+    pub amount: u64,
+    /// Reason code, see [`RefundReason`]
+    pub reason: u8,
+    /// Signer who issued the refund
+    pub refunded_by: Pubkey,
+    /// Unix timestamp when refunded
+    pub created_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Refund {
+    /// 8 (discriminator) + 32 (invoice) + 2 (index) + 8 (amount) + 1 (reason) +
+    /// 32 (refunded_by) + 8 (created_at) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 2 + 8 + 1 + 32 + 8 + 1;
+}
+
+/// Why a [`Refund`] was issued
+///
+/// The discriminants are stored on-chain and passed by clients, so they
+/// must never be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RefundReason {
+    /// The customer asked for their money back
+    CustomerRequest = 1,
+    /// The invoice was paid more than once
+    Duplicate = 2,
+    /// The order could not be fulfilled
+    OrderCancelled = 3,
+    /// The goods were returned or defective
+    ReturnedGoods = 4,
+    /// Any other reason
+    Other = 5,
+    /// An arbiter awarded the payer a share of a disputed escrow; not
+    /// accepted from clients
+    DisputeResolution = 6,
+}
+
+impl TryFrom<u8> for RefundReason {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(RefundReason::CustomerRequest),
+            2 => Ok(RefundReason::Duplicate),
+            3 => Ok(RefundReason::OrderCancelled),
+            4 => Ok(RefundReason::ReturnedGoods),
+            5 => Ok(RefundReason::Other),
+            6 => Ok(RefundReason::DisputeResolution),
+            _ => err!(IdentityError::InvalidRefundReason),
+        }
+    }
+}
+
+/// A location of a business, e.g. one of several cafés
+#[account]
+pub struct Store {
+    /// The identity the store belongs to
+    pub identity: Pubkey,
+    /// Index of the store within its identity
+    pub index: u32,
+    /// Store name, trimmed (max 64 characters and 128 bytes)
+    pub name: String,
+    /// Street address (max 128 bytes)
+    pub address: String,
+    /// Geohash of the location (max 12 characters)
+    pub geohash: Option<String>,
+    /// Wallet that receives native SOL payments instead of the identity's
+    pub settlement_sol: Option<Pubkey>,
+    /// Token accounts that receive payments instead of the identity's
+    pub settlement_accounts: Vec<SettlementDestination>,
+    /// Whether the store takes payments
+    pub active: bool,
+    /// Number of escrowed or disputed invoices of the store awaiting settlement
+    pub open_escrows: u32,
+    /// Unix timestamp when created
+    pub created_at: i64,
+    /// Unix timestamp when last updated
+    pub updated_at: i64,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl Store {
+    /// 8 (discriminator) + 32 (identity) + 4 (index) + 4+MAX_NAME_LENGTH (name) +
+    /// 4+MAX_STORE_ADDRESS_LENGTH (address) + 1+4+MAX_GEOHASH_LENGTH (geohash) +
+    /// 1+32 (settlement_sol) + 4+64*MAX_SETTLEMENT_ACCOUNTS (settlement_accounts) +
+    /// 1 (active) + 4 (open_escrows) + 8 (created_at) + 8 (updated_at) + 1 (bump)
+    pub const SIZE: usize = 8
+        + 32
+        + 4
+        + (4 + MAX_NAME_LENGTH)
+        + (4 + MAX_STORE_ADDRESS_LENGTH)
+        + (1 + 4 + MAX_GEOHASH_LENGTH)
+        + (1 + 32)
+        + (4 + SettlementDestination::SIZE * MAX_SETTLEMENT_ACCOUNTS)
+        + 1
+        + 4
+        + 8
+        + 8
+        + 1;
+}
+
+/// Check the address and geohash of a store
+fn validate_store_location(address: &str, geohash: &Option<String>) -> Result<()> {
+    require!(
+        address.len() <= MAX_STORE_ADDRESS_LENGTH,
+        IdentityError::InvalidStoreAddress
+    );
+    if let Some(geohash) = geohash {
+        require!(
+            !geohash.is_empty()
+                && geohash.len() <= MAX_GEOHASH_LENGTH
+                && geohash
+                    .bytes()
+                    .all(|b| b"0123456789bcdefghjkmnpqrstuvwxyz".contains(&b)),
+            IdentityError::InvalidGeohash
+        );
+    }
+    Ok(())
+}
+
+/// Token account that receives payments in `mint`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct SettlementDestination {
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+}
+
+impl SettlementDestination {
+    /// 32 (mint) + 32 (token_account)
+    pub const SIZE: usize = 32 + 32;
+
+    /// The destination for `mint` among `destinations`
+    pub fn find(destinations: &[SettlementDestination], mint: &Pubkey) -> Option<Pubkey> {
+        destinations
+            .iter()
+            .find(|destination| destination.mint == *mint)
+            .map(|destination| destination.token_account)
+    }
+}
+
+/// Validate a SOL destination and the (mint, token account) pairs in `remaining`
+fn parse_settlement<'info>(
+    settlement_sol: Option<Pubkey>,
+    remaining: &'info [AccountInfo<'info>],
+) -> Result<Vec<SettlementDestination>> {
+    require!(
+        remaining.len() % 2 == 0 && remaining.len() / 2 <= MAX_SETTLEMENT_ACCOUNTS,
+        IdentityError::InvalidSettlementAccounts
+    );
+    require!(
+        settlement_sol != Some(Pubkey::default()),
+        IdentityError::InvalidSettlementAccounts
+    );
+    let mut settlement_accounts: Vec<SettlementDestination> =
+        Vec::with_capacity(remaining.len() / 2);
+    for pair in remaining.chunks(2) {
+        let destination = validate_settlement_account(&pair[0], &pair[1])?;
+        require!(
+            settlement_accounts
+                .iter()
+                .all(|existing| existing.mint != destination.mint),
+            IdentityError::DuplicateSettlementMint
+        );
+        settlement_accounts.push(destination);
+    }
+    Ok(settlement_accounts)
+}
+
+/// Check that `token_account` is a token account of `mint`
+fn validate_settlement_account<'info>(
+    mint: &'info AccountInfo<'info>,
+    token_account: &'info AccountInfo<'info>,
+) -> Result<SettlementDestination> {
+    let mint_account = InterfaceAccount::<Mint>::try_from(mint)?;
+    let account = InterfaceAccount::<TokenAccount>::try_from(token_account)?;
+    require!(
+        account.mint == mint_account.key() && token_account.owner == mint.owner,
+        IdentityError::SettlementMintMismatch
+    );
+    Ok(SettlementDestination {
+        mint: mint.key(),
+        token_account: token_account.key(),
+    })
+}
+
+/// Patch for the optional profile fields of an identity
+///
+/// `None` keeps the stored value and an empty string clears it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ProfileUpdate {
+    pub description: Option<String>,
+    pub website: Option<String>,
+    pub support_contact: Option<String>,
+    pub country_code: Option<String>,
+    pub business_category: Option<String>,
+    pub opening_hours: Option<String>,
+}
+
+/// Apply one field of a [`ProfileUpdate`]
+fn patch_field(field: &mut Option<String>, value: Option<String>, max_len: usize) -> Result<()> {
+    if let Some(value) = value {
+        require!(value.len() <= max_len, IdentityError::ProfileFieldTooLong);
+        *field = (!value.is_empty()).then_some(value);
+    }
+    Ok(())
+}
+
+/// Reallocate a program-owned account to `new_len`
+///
+/// Missing rent is transferred from `payer`; lamports above the new
+/// rent-exempt minimum are returned to `refund_to`.
+fn resize_account<'info>(
+    account: &AccountInfo<'info>,
+    new_len: usize,
+    payer: &AccountInfo<'info>,
+    refund_to: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    if account.data_len() == new_len {
+        return Ok(());
+    }
+
+    let required = Rent::get()?.minimum_balance(new_len);
+    let current = account.lamports();
+    if required > current {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            required - current,
+        )?;
+    } else if current > required {
+        **account.try_borrow_mut_lamports()? -= current - required;
+        **refund_to.try_borrow_mut_lamports()? += current - required;
+    }
+
+    account.realloc(new_len, false)?;
+    Ok(())
+}
+
+/// Kind of entity behind an identity
+///
+/// The discriminants are stored on-chain and passed by clients, so they
+/// must never be renumbered. Accounts created before the type was
+/// selectable all hold `Business`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdentityType {
+    Business = 1,
+    SoleProprietor = 2,
+    Nonprofit = 3,
+    Individual = 4,
+    Platform = 5,
+}
+
+impl IdentityType {
+    /// Whether a logo must be set for this kind of identity
+    pub fn requires_logo(self) -> bool {
+        matches!(self, IdentityType::Nonprofit | IdentityType::Platform)
+    }
+
+    /// Whether this kind of identity may take payments and run a business
+    /// through stores, terminals, staff and invoices
+    ///
+    /// Individuals are customers and only hold a profile.
+    pub fn is_merchant(self) -> bool {
+        !matches!(self, IdentityType::Individual)
+    }
+}
+
+impl TryFrom<u8> for IdentityType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(IdentityType::Business),
+            2 => Ok(IdentityType::SoleProprietor),
+            3 => Ok(IdentityType::Nonprofit),
+            4 => Ok(IdentityType::Individual),
+            5 => Ok(IdentityType::Platform),
+            _ => err!(IdentityError::InvalidIdentityType),
+        }
+    }
+}
+
+/// Reverse lookup from the current authority to its identity PDA
+#[account]
+pub struct IdentityPointer {
+    /// The identity PDA owned by the wallet this pointer is seeded with
+    pub identity: Pubkey,
+    /// PDA bump seed
+    pub bump: u8,
+}
+
+impl IdentityPointer {
+    /// 8 (discriminator) + 32 (identity) + 1 (bump)
+    pub const SIZE: usize = 8 + 32 + 1;
+
+    /// Whether the pointer is unset or already points at `identity`
+    pub fn is_free_for(&self, identity: &Pubkey) -> bool {
+        self.identity == Pubkey::default() || self.identity == *identity
+    }
+}
+
+#[event]
+pub struct IdentityCreated {
+    pub identity: Pubkey,
+    pub authority: Pubkey,
+    pub identity_type: u8,
+    pub name: String,
+    pub logo_uri: String,
+    pub logo_sha256: Option<[u8; 32]>,
+    pub created_at: i64,
+}
+
+/// Emitted on every profile update, with the values before and after
+#[event]
+pub struct IdentityUpdated {
+    pub identity: Pubkey,
+    pub authority: Pubkey,
+    pub old_name: String,
+    pub new_name: String,
+    pub old_logo_uri: String,
+    pub new_logo_uri: String,
+    pub old_logo_sha256: Option<[u8; 32]>,
+    pub new_logo_sha256: Option<[u8; 32]>,
+    pub old_logo_mime: Option<String>,
+    pub new_logo_mime: Option<String>,
+    /// The profile patch as applied
+    pub profile: ProfileUpdate,
+    /// Stored profile fields before and after, `None` meaning unset
+    pub old_profile: ProfileUpdate,
+    pub new_profile: ProfileUpdate,
+    pub updated_at: i64,
+}
+
+#[event]
+pub struct TerminalRegistered {
+    pub identity: Pubkey,
+    pub terminal: Pubkey,
+    pub store: Option<Pubkey>,
+    pub device: Pubkey,
+    pub label: String,
+}
+
+#[event]
+pub struct TerminalRevoked {
+    pub identity: Pubkey,
+    pub terminal: Pubkey,
+    pub device: Pubkey,
+}
+
+#[event]
+pub struct TerminalClosed {
+    pub identity: Pubkey,
+    pub terminal: Pubkey,
+    pub device: Pubkey,
+}
+
+#[event]
+pub struct InvoiceCreated {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub reference: Pubkey,
+    pub store: Option<Pubkey>,
+    pub terminal: Option<Pubkey>,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub expires_at: Option<i64>,
+    pub escrow_release_window: Option<i64>,
+}
+
+#[event]
+pub struct InvoiceCancelled {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub reference: Pubkey,
+}
+
+/// Emitted when an expired or cancelled invoice is closed by the crank
+#[event]
+pub struct InvoiceClosed {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub reference: Pubkey,
+    pub caller: Pubkey,
+    pub bounty: u64,
+}
+
+/// Emitted when an invoice is paid, so a payment can be matched to its
+/// invoice without heuristics
+#[event]
+pub struct InvoicePaid {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub reference: Pubkey,
+    pub payer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct InvoiceRefunded {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub refund: Pubkey,
+    pub payer_token_account: Pubkey,
+    pub amount: u64,
+    pub reason: u8,
+    /// Total refunded on the invoice, including this refund
+    pub refunded_amount: u64,
+    pub refunded_by: Pubkey,
+}
+
+#[event]
+pub struct DisputeOpened {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub payer: Pubkey,
+}
+
+/// Emitted when an escrow is claimed by the merchant or split by the arbiter
+#[event]
+pub struct EscrowReleased {
+    pub identity: Pubkey,
+    pub invoice: Pubkey,
+    pub merchant_amount: u64,
+    pub payer_amount: u64,
+}
+
+/// Emitted when a store is created, updated or deactivated
+#[event]
+pub struct StoreUpdated {
+    pub identity: Pubkey,
+    pub store: Pubkey,
+    pub index: u32,
+    pub name: String,
+    pub address: String,
+    pub geohash: Option<String>,
+    pub active: bool,
+}
+
+#[event]
+pub struct StoreClosed {
+    pub identity: Pubkey,
+    pub store: Pubkey,
+    pub index: u32,
+}
+
+/// Emitted when the settlement destinations of an identity or store are replaced
+#[event]
+pub struct SettlementUpdated {
+    pub identity: Pubkey,
+    /// Set when the destinations of a store were replaced
+    pub store: Option<Pubkey>,
+    pub settlement_sol: Option<Pubkey>,
+    pub settlement_accounts: Vec<SettlementDestination>,
+}
+
+/// Emitted when an identity is closed, so indexers can drop the merchant
+#[event]
+pub struct IdentityClosed {
+    pub identity: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,
+    pub closed_at: i64,
+}
+
+#[event]
+pub struct AuthorityTransferProposed {
+    pub identity: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferCancelled {
+    pub identity: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub identity: Pubkey,
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+/// Emitted when guardians are set; an empty list means they were removed
+#[event]
+pub struct GuardiansUpdated {
+    pub identity: Pubkey,
+    pub guardians: Vec<Pubkey>,
+    pub threshold: u8,
+    pub timelock: i64,
+}
+
+#[event]
+pub struct RecoveryInitiated {
+    pub identity: Pubkey,
+    pub new_authority: Pubkey,
+    pub initiator: Pubkey,
+    pub unlocks_at: Option<i64>,
+}
+
+#[event]
+pub struct RecoveryApproved {
+    pub identity: Pubkey,
+    pub guardian: Pubkey,
+    pub approvals: u8,
+    pub unlocks_at: Option<i64>,
+}
+
+#[event]
+pub struct RecoveryCancelled {
+    pub identity: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+pub struct IdentityMigrated {
+    pub identity: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
+/// Emitted when an identity registers, changes or releases its @handle
+#[event]
+pub struct HandleChanged {
+    pub identity: Pubkey,
+    pub old_handle: Option<String>,
+    pub new_handle: Option<String>,
+}
+
+/// Emitted when a staff member is added or their permissions change
+#[event]
+pub struct StaffUpdated {
+    pub identity: Pubkey,
+    pub staff: Pubkey,
+    pub permissions: StaffPermissions,
+}
+
+#[event]
+pub struct StaffRemoved {
+    pub identity: Pubkey,
+    pub staff: Pubkey,
+}
+
+/// Emitted when a multisig is created or its members change
+#[event]
+pub struct MultisigUpdated {
+    pub multisig: Pubkey,
+    pub signer: Pubkey,
+    pub members: Vec<Pubkey>,
+    pub threshold: u8,
+    pub generation: u32,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub program_id: Pubkey,
+}
+
+#[event]
+pub struct ProposalApproved {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub member: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub index: u64,
+    pub executor: Pubkey,
+}
+
+#[event]
+pub struct ProposalCancelled {
+    pub multisig: Pubkey,
+    pub proposal: Pubkey,
+    pub index: u64,
+}
+
+#[event]
+pub struct ConfigInitialized {
+    pub admin: Pubkey,
+}
+
+#[event]
+pub struct AdminProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+#[event]
+pub struct AdminChanged {
+    pub previous_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+#[event]
+pub struct PausedChanged {
+    pub paused: u8,
+}
+
+#[event]
+pub struct CrankBountyChanged {
+    pub lamports: u64,
+}
+
+#[event]
+pub struct ArbiterChanged {
+    pub arbiter: Pubkey,
+}
+
+/// Emitted when a verifier is added to or removed from the allowlist
+#[event]
+pub struct VerifierChanged {
+    pub verifier: Pubkey,
+    pub allowed: bool,
+}
+
+/// Emitted when the admin reserves a handle or lifts its reservation
+#[event]
+pub struct HandleReservationChanged {
+    pub handle: String,
+    pub reserved: bool,
+}
+
+#[event]
+pub struct AttestationIssued {
+    pub attestation: Pubkey,
+    pub identity: Pubkey,
+    pub verifier: Pubkey,
+    pub claim_type: u8,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct AttestationRevoked {
+    pub attestation: Pubkey,
+    pub identity: Pubkey,
+    pub verifier: Pubkey,
+    pub claim_type: u8,
+}
+
+#[event]
+pub struct AttestationRemoved {
+    pub attestation: Pubkey,
+    pub identity: Pubkey,
+    pub verifier: Pubkey,
+    pub claim_type: u8,
+}
+
+#[error_code]
+pub enum IdentityError {
+    #[msg("Name must be 1-64 characters")]
+    InvalidNameLength,
+    #[msg("Logo URI must be at most 200 characters")]
+    InvalidLogoUriLength,
+    #[msg("Only the identity owner can perform this action")]
+    Unauthorized,
+    #[msg("New authority must differ from the current authority")]
+    InvalidNewAuthority,
+    #[msg("No authority transfer is pending")]
+    NoPendingAuthorityTransfer,
+    #[msg("Signer is not the pending authority")]
+    PendingAuthorityMismatch,
+    #[msg("Unknown identity type")]
+    InvalidIdentityType,
+    #[msg("A logo is required for this identity type")]
+    LogoRequired,
+    #[msg("Identity uses an old layout and must be migrated first")]
+    MigrationRequired,
+    #[msg("Identity already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Unsupported identity layout version")]
+    UnsupportedVersion,
+    #[msg("Identity was modified since it was read")]
+    StaleIdentity,
+    #[msg("Profile field exceeds its maximum length")]
+    ProfileFieldTooLong,
+    #[msg("Country code must be two uppercase letters (ISO 3166-1 alpha-2)")]
+    InvalidCountryCode,
+    #[msg("This name is already taken by another identity")]
+    NameTaken,
+    #[msg("The name record of the new name must be provided when renaming")]
+    NameRecordRequired,
+    #[msg("Handle must be 3-20 characters of a-z, 0-9 and _")]
+    InvalidHandle,
+    #[msg("This handle is already taken by another identity")]
+    HandleTaken,
+    #[msg("This handle is reserved")]
+    HandleReserved,
+    #[msg("Identity already has a handle")]
+    HandleAlreadySet,
+    #[msg("Identity has no handle")]
+    NoHandle,
+    #[msg("The handle record must be provided for an identity with a handle")]
+    HandleRecordRequired,
+    #[msg("Unknown attestation claim type")]
+    InvalidClaimType,
+    #[msg("Attestation expiry must be in the future")]
+    InvalidAttestationExpiry,
+    #[msg("Attestation is already revoked")]
+    AttestationRevoked,
+    #[msg("This instruction is paused by the program admin")]
+    ProgramPaused,
+    #[msg("Unknown pause flags")]
+    InvalidPauseFlags,
+    #[msg("Name must be at most 128 bytes")]
+    NameTooLong,
+    #[msg("Name must not contain control characters")]
+    NameHasControlCharacter,
+    #[msg("Name must not contain zero-width characters")]
+    NameHasZeroWidthCharacter,
+    #[msg("Name must not contain bidirectional formatting characters")]
+    NameHasBidiCharacter,
+    #[msg("Logo URI must be printable ASCII without spaces")]
+    LogoUriHasInvalidCharacter,
+    #[msg("Logo URI must start with https://, ar:// or ipfs://")]
+    UnsupportedLogoUriScheme,
+    #[msg("Logo hash and MIME type can only be set together with a logo URI")]
+    LogoCommitmentWithoutUri,
+    #[msg("Logo MIME type must be an image/* type of at most 32 characters")]
+    InvalidLogoMime,
+    #[msg("Identity has no logo hash to verify against")]
+    NoLogoCommitment,
+    #[msg("Logo does not match the committed hash")]
+    LogoHashMismatch,
+    #[msg("Staff roles must be a non-empty combination of known roles")]
+    InvalidStaffRoles,
+    #[msg("Staff expiry must be in the future")]
+    InvalidStaffExpiry,
+    #[msg("The identity authority cannot be added as staff")]
+    InvalidStaff,
+    #[msg("Amount exceeds the staff member's limit")]
+    StaffLimitExceeded,
+    #[msg("Multisig members must be 1 to 10 distinct keys, excluding its signer")]
+    InvalidMultisigMembers,
+    #[msg("Multisig threshold must be between 1 and the number of members")]
+    InvalidMultisigThreshold,
+    #[msg("Signer is not a member of the multisig")]
+    NotMultisigMember,
+    #[msg("Proposals may only target this program or the system program")]
+    UnsupportedProposalProgram,
+    #[msg("Proposal has too many accounts or too much data")]
+    ProposalTooLarge,
+    #[msg("Proposal was created before the multisig members changed")]
+    ProposalStale,
+    #[msg("Member has already approved this proposal")]
+    AlreadyApproved,
+    #[msg("Proposal does not have enough approvals")]
+    ThresholdNotMet,
+    #[msg("Proposal has already been executed")]
+    ProposalAlreadyExecuted,
+    #[msg("Remaining accounts do not match the proposal")]
+    ProposalAccountMismatch,
+    #[msg("Guardians must be 1 to 10 distinct keys, excluding the authority")]
+    InvalidGuardians,
+    #[msg("Guardian threshold must be between 1 and the number of guardians")]
+    InvalidGuardianThreshold,
+    #[msg("Recovery timelock must be between 1 and 30 days")]
+    InvalidRecoveryTimelock,
+    #[msg("Signer is not a guardian of this identity")]
+    NotGuardian,
+    #[msg("Recovery timelock has not passed")]
+    RecoveryTimelocked,
+    #[msg("Settlement accounts must be up to 8 (mint, token account) pairs")]
+    InvalidSettlementAccounts,
+    #[msg("Settlement token account does not belong to the mint")]
+    SettlementMintMismatch,
+    #[msg("Only one settlement destination per mint is allowed")]
+    DuplicateSettlementMint,
+    #[msg("Store address is too long")]
+    InvalidStoreAddress,
+    #[msg("Geohash must be 1 to 12 base32 characters")]
+    InvalidGeohash,
+    #[msg("Store is not active")]
+    StoreInactive,
+    #[msg("Terminal label must be 1 to 32 bytes")]
+    InvalidTerminalLabel,
+    #[msg("The identity authority cannot be registered as a terminal")]
+    InvalidTerminalDevice,
+    #[msg("Terminal has been revoked")]
+    TerminalRevoked,
+    #[msg("Invoice amount must be greater than zero")]
+    InvalidInvoiceAmount,
+    #[msg("Invoice memo is too long")]
+    InvoiceMemoTooLong,
+    #[msg("Invoice expiry must be in the future")]
+    InvalidInvoiceExpiry,
+    #[msg("Invalid invoice status")]
+    InvalidInvoiceStatus,
+    #[msg("No settlement destination for this mint")]
+    NoSettlementAccount,
+    #[msg("Invoice store does not match the terminal's store")]
+    TerminalStoreMismatch,
+    #[msg("Invoice is not open")]
+    InvoiceNotOpen,
+    #[msg("Invoice has expired")]
+    InvoiceExpired,
+    #[msg("Store does not match the invoice")]
+    InvoiceStoreMismatch,
+    #[msg("Invoice is neither expired nor cancelled")]
+    InvoiceNotExpired,
+    #[msg("Invoice has not been paid")]
+    InvoiceNotPaid,
+    #[msg("Refund amount must be greater than zero")]
+    InvalidRefundAmount,
+    #[msg("Refund exceeds the amount paid minus earlier refunds")]
+    RefundExceedsPaidAmount,
+    #[msg("Invalid refund reason")]
+    InvalidRefundReason,
+    #[msg("Token account does not belong to the invoice payer")]
+    RefundPayerMismatch,
+    #[msg("Escrow release window must be between 1 hour and 90 days")]
+    InvalidEscrowReleaseWindow,
+    #[msg("Escrowed invoices must be paid with pay_invoice_escrow")]
+    EscrowRequired,
+    #[msg("Invoice is not escrowed")]
+    NotEscrowInvoice,
+    #[msg("Invoice payment is not held in escrow")]
+    InvoiceNotEscrowed,
+    #[msg("Invoice is not disputed")]
+    InvoiceNotDisputed,
+    #[msg("Escrow release window has not passed")]
+    EscrowNotReleased,
+    #[msg("Escrow release window has passed")]
+    EscrowReleased,
+    #[msg("No arbiter is configured for disputes")]
+    NoArbiter,
+    #[msg("Payer share exceeds the invoice amount")]
+    InvalidDisputeSplit,
+    #[msg("Account is not the invoice payer")]
+    InvoicePayerMismatch,
+    #[msg("Guardians were set by a previous authority")]
+    StaleGuardians,
+    #[msg("Identity still has stores that must be closed first")]
+    IdentityHasStores,
+    #[msg("Identity has escrowed payments awaiting settlement")]
+    OpenEscrows,
+    #[msg("Staff managers can only grant permissions they hold")]
+    StaffGrantExceeded,
+    #[msg("A refund record is required when the payer gets a share")]
+    RefundRecordRequired,
+    #[msg("Website must be an https:// URL of printable ASCII")]
+    InvalidWebsite,
+    #[msg("Wallet already owns another identity")]
+    WalletHasIdentity,
+    #[msg("Store must be deactivated first")]
+    StoreActive,
+    #[msg("Device is already an active terminal of this identity")]
+    TerminalExists,
+    #[msg("Only merchant identities can do this, not individuals")]
+    NotAMerchant,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_identity() -> BusinessIdentityV1 {
+        BusinessIdentityV1 {
+            authority: Pubkey::new_unique(),
+            identity_type: 1,
+            name: "Wino Café".to_string(),
+            logo_uri: "ar://logo".to_string(),
+            created_at: 1_700_000_000,
+            updated_at: 1_700_000_100,
+            bump: 254,
+        }
+    }
+
+    fn account_data<T: AnchorSerialize>(layout: &T) -> Vec<u8> {
+        let mut data = BusinessIdentity::DISCRIMINATOR.to_vec();
+        layout.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn decodes_legacy_layout() {
+        let legacy = legacy_identity();
+        let data = account_data(&legacy);
+        assert_eq!(data[BusinessIdentity::VERSION_OFFSET], 1);
+        assert_eq!(
+            BusinessIdentity::stored_version(&data).unwrap(),
+            LEGACY_IDENTITY_VERSION
+        );
+
+        let identity = BusinessIdentity::try_deserialize_any_version(&data).unwrap();
+        assert_eq!(identity.authority, legacy.authority);
+        assert_eq!(identity.version, IDENTITY_VERSION);
+        assert_eq!(identity.identity_type, IdentityType::Business as u8);
+        assert_eq!(identity.name, legacy.name);
+        assert_eq!(identity.logo_uri, legacy.logo_uri);
+        assert_eq!(identity.created_at, legacy.created_at);
+        assert_eq!(identity.updated_at, legacy.updated_at);
+        assert_eq!(identity.bump, legacy.bump);
+        assert_eq!(identity.creator, legacy.authority);
+        assert!(identity.pending_authority.is_none());
+        assert!(identity.handle.is_none());
+        assert!(identity.settlement_accounts.is_empty());
+    }
+
+    #[test]
+    fn decodes_current_layout() {
+        let mut identity: BusinessIdentity = legacy_identity().into();
+        identity.identity_type = IdentityType::Nonprofit as u8;
+        identity.website = Some("https://wino.example".to_string());
+        identity.logo_sha256 = Some([7; 32]);
+        let mut data = Vec::new();
+        identity.try_serialize(&mut data).unwrap();
+        assert!(data.len() <= identity.space());
+
+        let decoded = BusinessIdentity::try_deserialize_any_version(&data).unwrap();
+        assert_eq!(decoded.version, IDENTITY_VERSION);
+        assert_eq!(decoded.identity_type, IdentityType::Nonprofit as u8);
+        assert_eq!(decoded.website, identity.website);
+        assert_eq!(decoded.logo_sha256, identity.logo_sha256);
+    }
+
+    #[test]
+    fn rejects_unknown_layout() {
+        let mut data = account_data(&legacy_identity());
+        data[BusinessIdentity::VERSION_OFFSET] = IDENTITY_VERSION + 1;
+        assert!(matches!(
+            BusinessIdentity::try_deserialize_any_version(&data),
+            Err(err) if err == IdentityError::UnsupportedVersion.into()
+        ));
+
+        data[..8].copy_from_slice(&NameRecord::DISCRIMINATOR);
+        assert!(BusinessIdentity::try_deserialize_any_version(&data).is_err());
+    }
+
+    fn assert_name_error(name: &str, expected: IdentityError) {
+        assert!(matches!(
+            validate_name(name),
+            Err(err) if err == expected.into()
+        ));
+    }
+
+    #[test]
+    fn rejects_deceptive_name_characters() {
+        assert_eq!(validate_name("  Wino Café \t").unwrap(), "Wino Café");
+
+        assert_name_error("Wino\u{0007}Café", IdentityError::NameHasControlCharacter);
+        assert_name_error("Wino\nCafé", IdentityError::NameHasControlCharacter);
+        assert_name_error("Wi\u{200B}no", IdentityError::NameHasZeroWidthCharacter);
+        assert_name_error("Wi\u{200D}no", IdentityError::NameHasZeroWidthCharacter);
+        assert_name_error("\u{FEFF}Wino", IdentityError::NameHasZeroWidthCharacter);
+        assert_name_error("Wino\u{202E}éfaC", IdentityError::NameHasBidiCharacter);
+        assert_name_error("\u{2067}Wino\u{2069}", IdentityError::NameHasBidiCharacter);
+        assert_name_error("Wino\u{061C}", IdentityError::NameHasBidiCharacter);
+    }
+
+    #[test]
+    fn limits_name_characters_and_bytes() {
+        assert_name_error("", IdentityError::InvalidNameLength);
+        assert_name_error(" \t ", IdentityError::InvalidNameLength);
+
+        // 64 two-byte characters fill both limits exactly
+        let name = "é".repeat(MAX_NAME_CHARS);
+        assert_eq!(name.len(), MAX_NAME_LENGTH);
+        assert_eq!(validate_name(&name).unwrap(), name);
+
+        assert_name_error(
+            &"a".repeat(MAX_NAME_CHARS + 1),
+            IdentityError::InvalidNameLength,
+        );
+        // 43 three-byte characters are within the character limit but
+        // take 129 bytes
+        assert_name_error(&"€".repeat(43), IdentityError::NameTooLong);
+    }
+
+    #[test]
+    fn allows_only_listed_logo_uri_schemes() {
+        validate_logo_uri("").unwrap();
+        for uri in ["https://wino.example/logo.png", "ar://abc", "ipfs://bafy"] {
+            validate_logo_uri(uri).unwrap();
+        }
+
+        for uri in [
+            "http://wino.example/logo.png",
+            "javascript:alert(1)",
+            "data:image/png;base64,AAAA",
+            "HTTPS://wino.example/logo.png",
+            "https://",
+            "ar://",
+        ] {
+            assert!(matches!(
+                validate_logo_uri(uri),
+                Err(err) if err == IdentityError::UnsupportedLogoUriScheme.into()
+            ));
+        }
+
+        assert!(matches!(
+            validate_logo_uri("https://wino.example/my logo.png"),
+            Err(err) if err == IdentityError::LogoUriHasInvalidCharacter.into()
+        ));
+        assert!(matches!(
+            validate_logo_uri(&format!("ar://{}", "a".repeat(MAX_LOGO_URI_LENGTH))),
+            Err(err) if err == IdentityError::InvalidLogoUriLength.into()
+        ));
+    }
+
+    #[test]
+    fn normalizes_names_before_hashing() {
+        let hash = normalized_name_hash("Wino Café");
+        // Case
+        assert_eq!(normalized_name_hash("WINO CAFÉ"), hash);
+        assert_eq!(normalized_name_hash("wino café"), hash);
+        // Surrounding whitespace
+        assert_eq!(normalized_name_hash("  Wino Café "), hash);
+        // Decomposed accent
+        assert_eq!(normalized_name_hash("Wino Cafe\u{0301}"), hash);
+        // Fullwidth compatibility forms
+        assert_eq!(normalized_name_hash("Ｗｉｎｏ Ｃａｆé"), hash);
+        // Ligature
+        assert_eq!(
+            normalized_name_hash("\u{FB01}ne wines"),
+            normalized_name_hash("Fine Wines")
+        );
+
+        assert_ne!(normalized_name_hash("Wino Cafe"), hash);
+        assert_ne!(normalized_name_hash("WinoCafé"), hash);
+    }
+
+    #[test]
+    fn verifies_logo_against_commitment() {
+        let mut identity: BusinessIdentity = legacy_identity().into();
+        assert!(matches!(
+            identity.verify_logo(b"abc"),
+            Err(err) if err == IdentityError::NoLogoCommitment.into()
+        ));
+
+        // SHA-256 of "abc" (FIPS 180-2 test vector)
+        identity.logo_sha256 = Some([
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ]);
+        identity.verify_logo(b"abc").unwrap();
+        assert!(matches!(
+            identity.verify_logo(b"abd"),
+            Err(err) if err == IdentityError::LogoHashMismatch.into()
+        ));
+        assert!(matches!(
+            identity.verify_logo(b""),
+            Err(err) if err == IdentityError::LogoHashMismatch.into()
+        ));
+    }
+
+    #[test]
+    fn website_must_be_https() {
+        let mut identity: BusinessIdentity = legacy_identity().into();
+        for website in [
+            "javascript:alert(1)",
+            "http://wino.example",
+            "https://",
+            "https://wino example",
+        ] {
+            let profile = ProfileUpdate {
+                website: Some(website.to_string()),
+                ..Default::default()
+            };
+            assert!(matches!(
+                identity.apply_profile(profile),
+                Err(err) if err == IdentityError::InvalidWebsite.into()
+            ));
+        }
+
+        let profile = ProfileUpdate {
+            website: Some("https://wino.example/shop".to_string()),
+            ..Default::default()
+        };
+        identity.apply_profile(profile).unwrap();
+        assert_eq!(
+            identity.website.as_deref(),
+            Some("https://wino.example/shop")
+        );
+    }
+
+    #[test]
+    fn migration_keeps_pointer_to_other_identity() {
+        let legacy = Pubkey::new_unique();
+        let mut pointer = IdentityPointer {
+            identity: Pubkey::default(),
+            bump: 255,
+        };
+        assert!(pointer.is_free_for(&legacy));
+
+        pointer.identity = legacy;
+        assert!(pointer.is_free_for(&legacy));
+
+        // The wallet created a current identity before migrating its
+        // legacy one
+        pointer.identity = Pubkey::new_unique();
+        assert!(!pointer.is_free_for(&legacy));
+    }
+
+    #[test]
+    fn migration_claims_registered_name() {
+        let legacy = legacy_identity();
+        let hash = BusinessIdentity::stored_name_hash(&account_data(&legacy)).unwrap();
+        assert_eq!(hash, normalized_name_hash(&legacy.name));
+
+        let current: BusinessIdentity = legacy.into();
+        assert_eq!(
+            BusinessIdentity::stored_name_hash(&account_data(&current)).unwrap(),
+            hash
+        );
+    }
+
+    #[test]
+    fn terminals_lapse_on_revocation_and_transfer() {
+        let mut identity: BusinessIdentity = legacy_identity().into();
+        let mut terminal = Terminal {
+            identity: Pubkey::new_unique(),
+            authority: identity.authority,
+            store: None,
+            device: Pubkey::new_unique(),
+            label: "Front counter".to_string(),
+            last_seen_slot: 0,
+            revoked: false,
+            created_at: 0,
+            bump: 255,
+        };
+        assert!(terminal.is_live(&identity));
+
+        terminal.revoked = true;
+        assert!(!terminal.is_live(&identity));
+
+        terminal.revoked = false;
+        identity.authority = Pubkey::new_unique();
+        assert!(!terminal.is_live(&identity));
+    }
+
+    #[test]
+    fn attestations_lapse_when_identity_changes() {
+        let mut identity: BusinessIdentity = legacy_identity().into();
+        let attestation = Attestation {
+            identity: Pubkey::new_unique(),
+            authority: identity.authority,
+            name_hash: normalized_name_hash(&identity.name),
+            identity_created_at: identity.created_at,
+            verifier: Pubkey::new_unique(),
+            claim_type: ClaimType::BusinessVerified as u8,
+            evidence_hash: [1; 32],
+            issued_at: 1_700_000_200,
+            expires_at: 1_800_000_000,
+            revoked: false,
+            bump: 255,
+        };
+        let now = 1_700_000_300;
+        assert!(attestation.is_valid(&identity, now));
+        assert!(!attestation.is_valid(&identity, attestation.expires_at));
+
+        identity.name = "WINO CAFÉ".to_string();
+        assert!(attestation.is_valid(&identity, now));
+        identity.name = "Other Café".to_string();
+        assert!(!attestation.is_valid(&identity, now));
+        identity.name = "Wino Café".to_string();
+
+        // Closed and created again by the same wallet under the same name
+        identity.created_at = 1_750_000_000;
+        assert!(!attestation.is_valid(&identity, now));
+        identity.created_at = attestation.identity_created_at;
+
+        identity.authority = Pubkey::new_unique();
+        assert!(!attestation.is_valid(&identity, now));
+    }
+
+    #[test]
+    fn only_merchants_take_payments() {
+        for (kind, merchant) in [
+            (IdentityType::Business, true),
+            (IdentityType::SoleProprietor, true),
+            (IdentityType::Nonprofit, true),
+            (IdentityType::Individual, false),
+            (IdentityType::Platform, true),
+        ] {
+            assert_eq!(kind.is_merchant(), merchant, "{kind:?}");
+            assert_eq!(IdentityType::try_from(kind as u8).unwrap(), kind);
+        }
+        assert!(IdentityType::try_from(0).is_err());
+        assert!(IdentityType::try_from(6).is_err());
+    }
+
+    #[test]
+    fn staff_grants_stay_within_manager_permissions() {
+        let manager = StaffPermissions {
+            roles: ROLE_CREATE_INVOICES | ROLE_MANAGE_STAFF,
+            expires_at: Some(2_000),
+            max_invoice_amount: Some(500),
+            max_refund_amount: None,
+        };
+        let grant = StaffPermissions {
+            roles: ROLE_CREATE_INVOICES,
+            expires_at: Some(1_000),
+            max_invoice_amount: Some(500),
+            max_refund_amount: Some(10),
+        };
+        assert!(grant.is_within(&manager));
+        assert!(manager.is_within(&manager));
+
+        let extra_role = StaffPermissions {
+            roles: ROLE_ISSUE_REFUNDS,
+            ..grant.clone()
+        };
+        assert!(!extra_role.is_within(&manager));
+        let unlimited = StaffPermissions {
+            max_invoice_amount: None,
+            ..grant.clone()
+        };
+        assert!(!unlimited.is_within(&manager));
+        let longer = StaffPermissions {
+            expires_at: Some(3_000),
+            ..grant.clone()
+        };
+        assert!(!longer.is_within(&manager));
+        let never_expires = StaffPermissions {
+            expires_at: None,
+            ..grant
+        };
+        assert!(!never_expires.is_within(&manager));
+    }
+
+    #[test]
+    fn handles_are_short_lowercase_and_url_safe() {
+        for handle in [
+            "abc",
+            "wino_cafe",
+            "cafe42",
+            "___",
+            &"a".repeat(MAX_HANDLE_LENGTH),
+        ] {
+            assert!(is_valid_handle(handle), "{handle}");
+        }
+
+        for handle in [
+            "",
+            "ab",
+            &"a".repeat(MAX_HANDLE_LENGTH + 1),
+            "Wino",
+            "wino-cafe",
+            "wino.cafe",
+            "wino cafe",
+            "wino/cafe",
+            "café",
+        ] {
+            assert!(!is_valid_handle(handle), "{handle}");
+        }
+    }
+
+    #[test]
+    fn multisig_members_are_distinct_and_meet_threshold() {
+        let signer = Pubkey::new_unique();
+        let [a, b, c] = [(); 3].map(|_| Pubkey::new_unique());
+        let mut multisig = Multisig {
+            create_key: Pubkey::new_unique(),
+            members: vec![],
+            threshold: 0,
+            generation: 0,
+            proposal_count: 0,
+            bump: 255,
+            signer_bump: 255,
+        };
+
+        multisig.set_members(vec![a, b, c], 2, signer).unwrap();
+        assert_eq!(multisig.members, vec![a, b, c]);
+        assert_eq!(multisig.threshold, 2);
+
+        let too_many = (0..=MAX_MULTISIG_MEMBERS)
+            .map(|_| Pubkey::new_unique())
+            .collect::<Vec<_>>();
+        for members in [vec![], too_many, vec![a, b, a], vec![a, signer]] {
+            assert!(matches!(
+                multisig.set_members(members, 1, signer),
+                Err(err) if err == IdentityError::InvalidMultisigMembers.into()
+            ));
+        }
+
+        for threshold in [0, 4] {
+            assert!(matches!(
+                multisig.set_members(vec![a, b, c], threshold, signer),
+                Err(err) if err == IdentityError::InvalidMultisigThreshold.into()
+            ));
+        }
+        // Rejected updates leave the members untouched
+        assert_eq!(multisig.members, vec![a, b, c]);
+        assert_eq!(multisig.threshold, 2);
+    }
+
+    #[test]
+    fn store_geohash_uses_base32_alphabet() {
+        validate_store_location("1 Rue de la Paix", &None).unwrap();
+        for geohash in [
+            "u09tvw0f6szy",
+            "s",
+            "0123456789bc",
+            "defghjkmnpqr",
+            "stuvwxyz",
+        ] {
+            validate_store_location("", &Some(geohash.to_string())).unwrap();
+        }
+
+        for geohash in [
+            "".to_string(),
+            "u09tvw0f6szyx".to_string(),
+            "u09a".to_string(),
+            "u09i".to_string(),
+            "u09l".to_string(),
+            "u09o".to_string(),
+            "U09TVW".to_string(),
+            "u09 tv".to_string(),
+        ] {
+            assert!(matches!(
+                validate_store_location("", &Some(geohash)),
+                Err(err) if err == IdentityError::InvalidGeohash.into()
+            ));
+        }
+
+        assert!(matches!(
+            validate_store_location(&"a".repeat(MAX_STORE_ADDRESS_LENGTH + 1), &None),
+            Err(err) if err == IdentityError::InvalidStoreAddress.into()
+        ));
+    }
 }